@@ -34,6 +34,8 @@ pub enum FrameDecoderState {
 #[derive(Debug)]
 pub struct FrameDecoder {
     cipher_mode: CipherMode,
+    // A cipher change requested part way through a frame, applied once that frame is decoded.
+    pending_cipher_mode: Option<CipherMode>,
     // If [LoginFrame]s decoded by this codec have 128 bits of zeroes trailing on the header.
     has_128b_trailer: bool,
     state: FrameDecoderState,
@@ -43,6 +45,7 @@ impl FrameDecoder {
     pub fn new(cipher_mode: CipherMode, has_128b_trailer: bool) -> Self {
         Self {
             cipher_mode,
+            pending_cipher_mode: None,
             has_128b_trailer,
             state: FrameDecoderState::Header,
         }
@@ -91,8 +94,17 @@ impl FrameDecoder {
         )))
     }
 
+    /// Replace the cipher used to decrypt frames. If the header of a frame has already been
+    /// consumed the change is deferred until that frame is decoded, so it always takes effect on a
+    /// frame boundary.
     pub fn set_cipher_mode(&mut self, cipher_mode: CipherMode) {
-        self.cipher_mode = cipher_mode;
+        match self.state {
+            FrameDecoderState::Header => {
+                self.cipher_mode = cipher_mode;
+                self.pending_cipher_mode = None;
+            }
+            FrameDecoderState::Data { .. } => self.pending_cipher_mode = Some(cipher_mode),
+        }
     }
 }
 
@@ -127,8 +139,13 @@ impl Decoder for FrameDecoder {
         self.state = FrameDecoderState::Header;
 
         let data = src.split_to(length);
-        let decrypted_data = crypto::decrypt(&self.cipher_mode, &data)
-            .map_err(|_| FrameDecoderError::InvalidCiphertext)?;
+        let decrypted_data = crypto::decrypt(&self.cipher_mode, &data);
+
+        if let Some(cipher_mode) = self.pending_cipher_mode.take() {
+            self.cipher_mode = cipher_mode;
+        }
+
+        let decrypted_data = decrypted_data.map_err(|_| FrameDecoderError::InvalidCiphertext)?;
 
         Ok(Some(Frame {
             counter,
@@ -137,3 +154,82 @@ impl Decoder for FrameDecoder {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+    use openssl::rsa::{Padding, Rsa};
+
+    use super::*;
+
+    const CWC_KEY: [u8; 16] = *b"0123456789abcdef";
+
+    fn frame(global_counter: u16, counter: u32, ciphertext: &[u8]) -> BytesMut {
+        let total_len = (ciphertext.len() + crate::frame::LOGIN_HEADER_SIZE) as u32;
+        let mut frame = BytesMut::new();
+
+        frame.put_u16(total_len as u16 - 2);
+        frame.put_u16(global_counter);
+        frame.put_u16(0);
+        frame.put_u32(total_len - 14);
+        frame.put_u32(total_len - 14);
+        frame.put_u32(0x0c);
+        frame.put_u32(0);
+        frame.put_u32_le(counter);
+        frame.put(ciphertext);
+        frame
+    }
+
+    /// A decoder expecting RSA, and an RSA frame followed by a CWC frame in one buffer.
+    fn rsa_then_cwc() -> (FrameDecoder, BytesMut) {
+        let rsa = Rsa::generate(1024).unwrap();
+        let rsa_mode = CipherMode::rsa_pkcs1_oeap(&rsa.private_key_to_pem().unwrap());
+        let mut rsa_ciphertext = vec![0u8; rsa.size() as usize];
+        let len = rsa
+            .public_encrypt(b"handshake", &mut rsa_ciphertext, Padding::PKCS1_OAEP)
+            .unwrap();
+        rsa_ciphertext.truncate(len);
+
+        let cwc_ciphertext =
+            crypto::encrypt(&CipherMode::aes128_cwc(&CWC_KEY), b"first cwc frame").unwrap();
+
+        let mut buf = frame(1, 1, &rsa_ciphertext);
+        buf.extend_from_slice(&frame(2, 2, &cwc_ciphertext));
+
+        (FrameDecoder::new(rsa_mode, false), buf)
+    }
+
+    #[test]
+    fn cipher_switch_between_buffered_frames() {
+        let (mut decoder, mut buf) = rsa_then_cwc();
+
+        let first = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&first.data[..], b"handshake");
+        assert_eq!((first.global_counter, first.counter), (1, 1));
+
+        decoder.set_cipher_mode(CipherMode::aes128_cwc(&CWC_KEY));
+
+        let second = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&second.data[..], b"first cwc frame");
+        assert_eq!((second.global_counter, second.counter), (2, 2));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cipher_switch_during_a_partial_frame_waits_for_its_end() {
+        let (mut decoder, mut rest) = rsa_then_cwc();
+        let mut buf = rest.split_to(crate::frame::LOGIN_HEADER_SIZE + 10);
+
+        // The RSA frame's header has been consumed, so the switch has to wait for its body.
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+        decoder.set_cipher_mode(CipherMode::aes128_cwc(&CWC_KEY));
+        buf.extend_from_slice(&rest);
+
+        let first = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&first.data[..], b"handshake");
+
+        let second = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&second.data[..], b"first cwc frame");
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+    }
+}
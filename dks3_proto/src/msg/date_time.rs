@@ -0,0 +1,225 @@
+//! Conversions between the protocol's `DateTime` and chrono's.
+//!
+//! We assume the protocol's date and time fields hold the local time of whoever produced the
+//! timestamp, and that `tzdiff` holds how far that local time is ahead of UTC in minutes. None of
+//! this has been checked against the client yet. Offsets behind UTC are stored as the two's
+//! complement of the negative number of minutes, since the field is unsigned.
+
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Offset, TimeZone, Timelike, Utc};
+use thiserror::Error;
+
+use crate::msg::common;
+
+const MAX_OFFSET_MINUTES: i32 = 24 * 60;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DateTimeError {
+    #[error("date time has an out of range {field} of {value}")]
+    OutOfRange { field: &'static str, value: u32 },
+
+    #[error("date time has a day that doesn't exist: {year}-{month}-{day}")]
+    InvalidDate { year: u32, month: u32, day: u32 },
+
+    #[error("date time has an offset from UTC of {0} minutes, which is more than a day")]
+    InvalidOffset(i32),
+}
+
+/// Converts a chrono date time into the protocol's, keeping its offset from UTC. Anything finer
+/// than a second is dropped. Years before 0 can't be represented and become 0.
+impl<Tz: TimeZone> From<DateTime<Tz>> for common::DateTime {
+    fn from(date_time: DateTime<Tz>) -> Self {
+        let offset_minutes = date_time.offset().fix().local_minus_utc() / 60;
+        let local = date_time.naive_local();
+
+        Self {
+            year: local.year().max(0) as u32,
+            month: local.month(),
+            day: local.day(),
+            hours: local.hour(),
+            minutes: local.minute(),
+            seconds: local.second(),
+            tzdiff: offset_minutes as u32,
+        }
+    }
+}
+
+fn check_range(field: &'static str, value: u32, min: u32, max: u32) -> Result<u32, DateTimeError> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(DateTimeError::OutOfRange { field, value })
+    }
+}
+
+impl TryFrom<common::DateTime> for DateTime<FixedOffset> {
+    type Error = DateTimeError;
+
+    fn try_from(date_time: common::DateTime) -> Result<Self, Self::Error> {
+        let month = check_range("month", date_time.month, 1, 12)?;
+        let day = check_range("day", date_time.day, 1, 31)?;
+        let hours = check_range("hours", date_time.hours, 0, 23)?;
+        let minutes = check_range("minutes", date_time.minutes, 0, 59)?;
+        let seconds = check_range("seconds", date_time.seconds, 0, 59)?;
+
+        let offset_minutes = date_time.tzdiff as i32;
+        let offset = Some(offset_minutes)
+            .filter(|minutes| (1 - MAX_OFFSET_MINUTES..MAX_OFFSET_MINUTES).contains(minutes))
+            .and_then(|minutes| FixedOffset::east_opt(minutes * 60))
+            .ok_or(DateTimeError::InvalidOffset(offset_minutes))?;
+
+        let year = date_time.year;
+        let invalid_date = || DateTimeError::InvalidDate { year, month, day };
+        let date = i32::try_from(year)
+            .ok()
+            .and_then(|year| NaiveDate::from_ymd_opt(year, month, day))
+            .ok_or_else(invalid_date)?;
+        let local = date.and_hms(hours, minutes, seconds);
+
+        offset
+            .from_local_datetime(&local)
+            .single()
+            .ok_or_else(invalid_date)
+    }
+}
+
+impl TryFrom<common::DateTime> for DateTime<Utc> {
+    type Error = DateTimeError;
+
+    fn try_from(date_time: common::DateTime) -> Result<Self, Self::Error> {
+        DateTime::<FixedOffset>::try_from(date_time).map(|date_time| date_time.with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proto(
+        (year, month, day): (u32, u32, u32),
+        (hours, minutes, seconds): (u32, u32, u32),
+        tzdiff: i32,
+    ) -> common::DateTime {
+        common::DateTime {
+            year,
+            month,
+            day,
+            hours,
+            minutes,
+            seconds,
+            tzdiff: tzdiff as u32,
+        }
+    }
+
+    #[test]
+    fn utc_has_no_offset() {
+        let date_time = Utc.ymd(2021, 3, 14).and_hms(15, 9, 26);
+
+        assert_eq!(
+            common::DateTime::from(date_time),
+            proto((2021, 3, 14), (15, 9, 26), 0)
+        );
+    }
+
+    #[test]
+    fn fields_are_local_time_and_tzdiff_is_minutes_ahead_of_utc() {
+        let tokyo = FixedOffset::east(9 * 3600)
+            .ymd(2021, 3, 15)
+            .and_hms(0, 30, 0);
+
+        assert_eq!(
+            common::DateTime::from(tokyo),
+            proto((2021, 3, 15), (0, 30, 0), 540)
+        );
+    }
+
+    #[test]
+    fn offsets_behind_utc_are_twos_complement() {
+        let new_york = FixedOffset::west(5 * 3600)
+            .ymd(2021, 3, 14)
+            .and_hms(10, 9, 26);
+        let converted = common::DateTime::from(new_york);
+
+        assert_eq!(converted.tzdiff, 0xFFFF_FED4);
+        assert_eq!(converted, proto((2021, 3, 14), (10, 9, 26), -300));
+    }
+
+    #[test]
+    fn converts_back_to_the_same_instant() {
+        let utc = Utc.ymd(2021, 3, 14).and_hms(15, 9, 26);
+
+        for offset in &[0, 540, -300, 345] {
+            let offset = FixedOffset::east(offset * 60);
+            let local = utc.with_timezone(&offset);
+            let converted = common::DateTime::from(local);
+
+            assert_eq!(
+                DateTime::<FixedOffset>::try_from(converted.clone()),
+                Ok(local)
+            );
+            assert_eq!(DateTime::<Utc>::try_from(converted), Ok(utc));
+        }
+    }
+
+    #[test]
+    fn drops_fractions_of_a_second() {
+        let date_time = Utc.ymd(2021, 3, 14).and_hms_milli(15, 9, 26, 535);
+        let converted = common::DateTime::from(date_time);
+
+        assert_eq!(
+            DateTime::<Utc>::try_from(converted),
+            Ok(Utc.ymd(2021, 3, 14).and_hms(15, 9, 26))
+        );
+    }
+
+    #[test]
+    fn accepts_leap_days() {
+        let leap_day = proto((2020, 2, 29), (0, 0, 0), 0);
+
+        assert_eq!(
+            DateTime::<Utc>::try_from(leap_day),
+            Ok(Utc.ymd(2020, 2, 29).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let cases = [
+            (proto((2021, 0, 1), (0, 0, 0), 0), "month", 0),
+            (proto((2021, 13, 1), (0, 0, 0), 0), "month", 13),
+            (proto((2021, 1, 0), (0, 0, 0), 0), "day", 0),
+            (proto((2021, 1, 32), (0, 0, 0), 0), "day", 32),
+            (proto((2021, 1, 1), (24, 0, 0), 0), "hours", 24),
+            (proto((2021, 1, 1), (0, 60, 0), 0), "minutes", 60),
+            (proto((2021, 1, 1), (0, 0, 60), 0), "seconds", 60),
+        ];
+
+        for (date_time, field, value) in cases.iter().cloned() {
+            assert_eq!(
+                DateTime::<Utc>::try_from(date_time),
+                Err(DateTimeError::OutOfRange { field, value })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_dates_that_dont_exist() {
+        for &(year, month, day) in &[(2021, 2, 29), (2021, 4, 31), (u32::MAX, 1, 1)] {
+            assert_eq!(
+                DateTime::<Utc>::try_from(proto((year, month, day), (0, 0, 0), 0)),
+                Err(DateTimeError::InvalidDate { year, month, day })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_of_a_day_or_more() {
+        for &tzdiff in &[1440, -1440, i32::MAX, i32::MIN] {
+            assert_eq!(
+                DateTime::<Utc>::try_from(proto((2021, 1, 1), (0, 0, 0), tzdiff)),
+                Err(DateTimeError::InvalidOffset(tzdiff))
+            );
+        }
+    }
+}
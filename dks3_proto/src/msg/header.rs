@@ -0,0 +1,186 @@
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+
+/// Declares [MessageType] along with the conversion back from its wire value, so each variant
+/// and its discriminant are only listed once.
+macro_rules! message_types {
+    ($($(#[$attr:meta])* $variant:ident = $value:expr,)*) => {
+        /// Identifies the request or push carried by a game service message.
+        ///
+        /// These are our own numbering until the client's message table has been mapped. They are
+        /// only used on the game port and must stay in sync with the client side of the game
+        /// protocol.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[repr(u32)]
+        pub enum MessageType {
+            $($(#[$attr])* $variant = $value,)*
+        }
+
+        impl MessageType {
+            /// Every message type, in declaration order.
+            pub const ALL: &'static [MessageType] = &[$(MessageType::$variant,)*];
+        }
+
+        impl TryFrom<u32> for MessageType {
+            type Error = u32;
+
+            fn try_from(value: u32) -> Result<Self, Self::Error> {
+                match value {
+                    $(v if v == MessageType::$variant as u32 => Ok(MessageType::$variant),)*
+                    _ => Err(value),
+                }
+            }
+        }
+    };
+}
+
+message_types! {
+    /// A response to the request with the same message index.
+    Reply = 0x0000,
+
+    RequestUpdatePlayerStatus = 0x0300,
+    RequestGetAnnounceMessageList = 0x0301,
+    RequestUpdatePlayerCharacter = 0x0302,
+    RequestCreateBloodMessage = 0x0366,
+    RequestRemoveBloodMessage = 0x0367,
+    RequestReentryBloodMessage = 0x0368,
+    RequestEvaluateBloodMessage = 0x036A,
+    RequestGetBloodMessageList = 0x036B,
+    RequestCreateBloodstain = 0x0370,
+    RequestGetBloodstainList = 0x0371,
+    RequestGetDeadingGhost = 0x0372,
+    RequestCreateGhostData = 0x0380,
+    RequestGetGhostDataList = 0x0381,
+    RequestCreateSign = 0x0390,
+    RequestGetSignList = 0x0391,
+    RequestSummonSign = 0x0392,
+    RequestAcceptSign = 0x0393,
+    RequestRejectSign = 0x0394,
+    RequestUpdateSign = 0x0395,
+    RequestRemoveSign = 0x0396,
+    RequestGetBreakInTargetList = 0x03A0,
+    RequestBreakInTarget = 0x03A1,
+    RequestAcceptBreakIn = 0x03A2,
+    RequestRejectBreakIn = 0x03A3,
+    RequestNotifyLeaveGuestPlayer = 0x03A4,
+    RequestRegisterBossDefender = 0x03B0,
+    RequestUnregisterBossDefender = 0x03B1,
+    RequestSummonBossDefender = 0x03B2,
+    RequestAcceptBossDefender = 0x03B3,
+    RequestRejectBossDefender = 0x03B4,
+    RequestRegisterQuickMatch = 0x03C0,
+    RequestSearchQuickMatch = 0x03C1,
+    RequestUnregisterQuickMatch = 0x03C2,
+    RequestSendQuickMatchResult = 0x03C3,
+    RequestGetQuickMatchRanking = 0x03C4,
+    RequestRegisterRankingData = 0x03D0,
+    RequestGetRankingData = 0x03D1,
+    RequestCountRankingData = 0x03D2,
+    RequestGetCurrentRank = 0x03D3,
+    RequestAcceptCovenantSummon = 0x03E0,
+    RequestRejectCovenantSummon = 0x03E1,
+
+    PushRequestEvaluateBloodMessage = 0x0401,
+    PushRequestSummonSign = 0x0402,
+    PushRequestAcceptSign = 0x0403,
+    PushRequestRejectSign = 0x0404,
+    PushRequestBreakInTarget = 0x0405,
+    PushRequestBreakInResult = 0x0406,
+    PushRequestCovenantSummon = 0x0407,
+    PushRequestSummonBossDefender = 0x0408,
+    PushRequestBossDefenderResult = 0x0409,
+    PushRequestQuickMatchFound = 0x040A,
+}
+
+#[derive(Debug, Error)]
+pub enum MessageHeaderError {
+    #[error("message was too short to contain a header")]
+    Truncated,
+
+    #[error("message header had an unexpected size of {0} bytes")]
+    InvalidSize(u32),
+}
+
+/// The header in front of every protobuf message exchanged with the game service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub msg_type: u32,
+    pub msg_index: u32,
+}
+
+impl MessageHeader {
+    pub const SIZE: usize = 12;
+
+    pub fn new(msg_type: MessageType, msg_index: u32) -> Self {
+        Self {
+            msg_type: msg_type as u32,
+            msg_index,
+        }
+    }
+
+    pub fn message_type(&self) -> Result<MessageType, u32> {
+        MessageType::try_from(self.msg_type)
+    }
+
+    /// Read a header from the front of `src`, leaving only the message body behind.
+    pub fn decode(src: &mut BytesMut) -> Result<Self, MessageHeaderError> {
+        if src.len() < Self::SIZE {
+            return Err(MessageHeaderError::Truncated);
+        }
+
+        let header_size = src.get_u32();
+        if header_size as usize != Self::SIZE {
+            return Err(MessageHeaderError::InvalidSize(header_size));
+        }
+
+        Ok(Self {
+            msg_type: src.get_u32(),
+            msg_index: src.get_u32(),
+        })
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.put_u32(Self::SIZE as u32);
+        dst.put_u32(self.msg_type);
+        dst.put_u32(self.msg_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_message_type_round_trips() {
+        for &msg_type in MessageType::ALL {
+            assert_eq!(MessageType::try_from(msg_type as u32), Ok(msg_type));
+        }
+    }
+
+    #[test]
+    fn message_type_values_are_unique() {
+        let mut values: Vec<u32> = MessageType::ALL.iter().map(|&t| t as u32).collect();
+        values.sort_unstable();
+        values.dedup();
+
+        assert_eq!(values.len(), MessageType::ALL.len());
+    }
+
+    #[test]
+    fn unknown_message_type_is_rejected() {
+        assert_eq!(MessageType::try_from(0xFFFF), Err(0xFFFF));
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = MessageHeader::new(MessageType::RequestGetSignList, 7);
+        let mut buf = BytesMut::new();
+        header.encode(&mut buf);
+
+        assert_eq!(buf.len(), MessageHeader::SIZE);
+        assert_eq!(MessageHeader::decode(&mut buf).unwrap(), header);
+        assert!(buf.is_empty());
+    }
+}
@@ -1,3 +1,9 @@
+pub use date_time::DateTimeError;
+pub use header::{MessageHeader, MessageHeaderError, MessageType};
+
+mod date_time;
+mod header;
+
 pub mod frpg2_request {
     include!(concat!(env!("OUT_DIR"), "/dks3.frpg2_request.rs"));
 }
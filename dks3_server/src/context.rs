@@ -1,7 +1,19 @@
 use std::sync::Arc;
 
+use crate::matchmaking::announcement::AnnouncementStore;
+use crate::matchmaking::arena_rating::ArenaRatingStore;
+use crate::matchmaking::blood_message::BloodMessageStore;
+use crate::matchmaking::bloodstain::BloodstainStore;
+use crate::matchmaking::boss_defender::BossDefenderStore;
+use crate::matchmaking::character::CharacterStore;
+use crate::matchmaking::covenant::{CovenantCooldowns, CovenantSummons};
+use crate::matchmaking::ghost::GhostStore;
+use crate::matchmaking::quick_match::QuickMatchStore;
+use crate::matchmaking::ranking::RankingStore;
+use crate::matchmaking::session::SessionRegistry;
+use crate::matchmaking::sign::SignStore;
+use crate::matchmaking::world::WorldStore;
 use crate::Config;
-use tokio::sync::RwLock;
 
 #[derive(Debug, Clone)]
 pub struct MatchmakingDb {
@@ -11,21 +23,128 @@ pub struct MatchmakingDb {
 
 impl MatchmakingDb {
     pub fn new(config: Config) -> Self {
-        Self {
-            config,
-            shared: Default::default(),
-        }
+        let shared = Arc::new(Shared {
+            sessions: Default::default(),
+            blood_messages: BloodMessageStore::load(
+                config.blood_message_max_per_area,
+                config.blood_message_max_per_player,
+                config.data_path("blood_messages.json"),
+            )
+            .expect("Unable to load blood messages"),
+            bloodstains: BloodstainStore::new(config.bloodstain_limits()),
+            ghosts: GhostStore::new(config.ghost_limits()),
+            signs: Default::default(),
+            worlds: Default::default(),
+            covenant_cooldowns: Default::default(),
+            covenant_summons: Default::default(),
+            boss_defenders: Default::default(),
+            quick_matches: Default::default(),
+            arena_ratings: ArenaRatingStore::load(
+                config.elo_settings(),
+                config.data_path("arena_ratings.json"),
+            )
+            .expect("Unable to load arena ratings"),
+            rankings: RankingStore::load(
+                config.ranking_limits(),
+                config.data_path("rankings.json"),
+            )
+            .expect("Unable to load ranking boards"),
+            characters: CharacterStore::new(
+                config.character_limits(),
+                config.data_path("characters"),
+            ),
+            announcements: AnnouncementStore::load(config.announcements_file.clone())
+                .expect("Unable to load announcements"),
+        });
+
+        Self { config, shared }
+    }
+
+    /// Start the background tasks that store persistent state as it changes and reload the
+    /// announcements file.
+    pub fn spawn_background_tasks(&self) {
+        self.blood_messages().spawn_writer();
+        self.arena_ratings().spawn_writer();
+        self.rankings().spawn_writer();
+        self.characters().spawn_writer();
+        self.announcements().spawn_reloader();
     }
 
     pub fn config(&self) -> &Config {
         &self.config
     }
-}
 
-#[derive(Default, Debug)]
-pub struct MatchmakingState {}
+    pub fn sessions(&self) -> &SessionRegistry {
+        &self.shared.sessions
+    }
+
+    pub fn blood_messages(&self) -> &BloodMessageStore {
+        &self.shared.blood_messages
+    }
+
+    pub fn bloodstains(&self) -> &BloodstainStore {
+        &self.shared.bloodstains
+    }
+
+    pub fn ghosts(&self) -> &GhostStore {
+        &self.shared.ghosts
+    }
+
+    pub fn signs(&self) -> &SignStore {
+        &self.shared.signs
+    }
+
+    pub fn worlds(&self) -> &WorldStore {
+        &self.shared.worlds
+    }
+
+    pub fn covenant_cooldowns(&self) -> &CovenantCooldowns {
+        &self.shared.covenant_cooldowns
+    }
+
+    pub fn covenant_summons(&self) -> &CovenantSummons {
+        &self.shared.covenant_summons
+    }
+
+    pub fn boss_defenders(&self) -> &BossDefenderStore {
+        &self.shared.boss_defenders
+    }
+
+    pub fn quick_matches(&self) -> &QuickMatchStore {
+        &self.shared.quick_matches
+    }
+
+    pub fn arena_ratings(&self) -> &ArenaRatingStore {
+        &self.shared.arena_ratings
+    }
+
+    pub fn rankings(&self) -> &RankingStore {
+        &self.shared.rankings
+    }
+
+    pub fn characters(&self) -> &CharacterStore {
+        &self.shared.characters
+    }
+
+    pub fn announcements(&self) -> &AnnouncementStore {
+        &self.shared.announcements
+    }
+}
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Shared {
-    state: RwLock<MatchmakingState>,
+    sessions: SessionRegistry,
+    blood_messages: BloodMessageStore,
+    bloodstains: BloodstainStore,
+    ghosts: GhostStore,
+    signs: SignStore,
+    worlds: WorldStore,
+    covenant_cooldowns: CovenantCooldowns,
+    covenant_summons: CovenantSummons,
+    boss_defenders: BossDefenderStore,
+    quick_matches: QuickMatchStore,
+    arena_ratings: ArenaRatingStore,
+    rankings: RankingStore,
+    characters: CharacterStore,
+    announcements: AnnouncementStore,
 }
@@ -2,16 +2,97 @@ extern crate config;
 extern crate tokio;
 
 use std::clone::Clone;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use tracing::{error, info};
+use thiserror::Error;
+use tracing::{error, info, warn};
 use tracing_subscriber::layer::SubscriberExt;
 
 use crate::context::MatchmakingDb;
+use crate::matchmaking::arena_rating::EloSettings;
+use crate::matchmaking::bloodstain::BloodstainLimits;
+use crate::matchmaking::character::CharacterLimits;
+use crate::matchmaking::covenant::CovenantSettings;
+use crate::matchmaking::ghost::GhostLimits;
+use crate::matchmaking::quick_match::RatingWindow;
+use crate::matchmaking::ranking::RankingLimits;
+use crate::matchmaking::rules::MatchingRules;
+use crate::net::limiter::ConnectionLimits;
 use crate::net::server::TcpServer;
 
 mod context;
+mod matchmaking;
 mod net;
 mod service;
+mod storage;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{0} is missing from the config file")]
+    Missing(String),
+
+    #[error("{key} in the config file must be {expected}")]
+    Invalid { key: String, expected: String },
+}
+
+impl ConfigError {
+    fn invalid(key: &str, expected: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            key: key.to_string(),
+            expected: expected.into(),
+        }
+    }
+}
+
+/// Read an integer setting, or None if it isn't set.
+fn read_int(
+    config_file: &config::Config,
+    key: &str,
+) -> std::result::Result<Option<i64>, ConfigError> {
+    match config_file.get_int(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(_) => Err(ConfigError::invalid(key, "a whole number")),
+    }
+}
+
+/// Read a count or size, which has to be above zero, or `default` if it isn't set.
+fn read_limit<T: TryFrom<i64>>(
+    config_file: &config::Config,
+    key: &str,
+    default: T,
+) -> std::result::Result<T, ConfigError> {
+    match read_int(config_file, key)? {
+        Some(value) if value > 0 => {
+            T::try_from(value).map_err(|_| ConfigError::invalid(key, "a smaller number"))
+        }
+        Some(_) => Err(ConfigError::invalid(key, "above zero")),
+        None => Ok(default),
+    }
+}
+
+/// Read a rate or amount that has to be above zero, or `default` if it isn't set.
+fn read_positive_float(
+    config_file: &config::Config,
+    key: &str,
+    default: f64,
+) -> std::result::Result<f64, ConfigError> {
+    match config_file.get_float(key) {
+        Ok(value) if value > 0.0 => Ok(value),
+        Ok(_) => Err(ConfigError::invalid(key, "above zero")),
+        Err(config::ConfigError::NotFound(_)) => Ok(default),
+        Err(_) => Err(ConfigError::invalid(key, "a number")),
+    }
+}
+
+/// Read a port number that has to be set.
+fn read_port(config_file: &config::Config, key: &str) -> std::result::Result<u16, ConfigError> {
+    let port = read_int(config_file, key)?.ok_or_else(|| ConfigError::Missing(key.to_string()))?;
+
+    u16::try_from(port).map_err(|_| ConfigError::invalid(key, "a port number"))
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -19,29 +100,132 @@ pub struct Config {
     login_port: u16,
     auth_port: u16,
     game_port: u16,
+    experimental_game_service: bool,
     rsa_private_key: String,
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    accept_rate_per_ip: f64,
+    accept_burst_per_ip: f64,
+    login_proxy_protocol: bool,
+    auth_proxy_protocol: bool,
+    game_proxy_protocol: bool,
+    blood_message_max_per_area: usize,
+    blood_message_max_per_player: usize,
+    blood_message_list_max: usize,
+    bloodstain_max_per_area: usize,
+    bloodstain_max_age_mins: i64,
+    bloodstain_max_data_size: usize,
+    bloodstain_max_replay_size: usize,
+    bloodstain_list_max: usize,
+    ghost_max_per_area: usize,
+    ghost_memory_budget_mb: usize,
+    ghost_max_size: usize,
+    ghost_list_max: usize,
+    sign_list_max: usize,
+    max_phantoms_per_world: u32,
+    break_in_target_list_max: usize,
+    matching_rules: MatchingRules,
+    covenants: CovenantSettings,
+    data_dir: PathBuf,
+    arena_rating_initial: f64,
+    arena_rating_k_factor: f64,
+    arena_rating_window: f64,
+    arena_rating_window_growth_per_min: f64,
+    arena_rating_window_max: f64,
+    arena_ranking_list_max: usize,
+    ranking_list_max: usize,
+    ranking_board_count: u32,
+    ranking_max_data_size: usize,
+    character_slot_count: u32,
+    character_max_data_size: usize,
+    announcements_file: PathBuf,
 }
 
 impl Config {
-    pub fn new(config_file: config::Config) -> Config {
-        Config {
+    pub fn new(config_file: config::Config) -> std::result::Result<Config, ConfigError> {
+        Ok(Config {
             server_ip: config_file
                 .get_str("server_ip")
-                .expect("Could not read server_ip from config file"),
-            login_port: config_file
-                .get_int("login_port")
-                .expect("Could not read login_port from config file")
-                as u16,
-            auth_port: config_file
-                .get_int("auth_port")
-                .expect("Could not read auth_port from config file") as u16,
-            game_port: config_file
-                .get_int("game_port")
-                .expect("Could not read game_port from config file") as u16,
+                .map_err(|_| ConfigError::Missing("server_ip".to_string()))?,
+            login_port: read_port(&config_file, "login_port")?,
+            auth_port: read_port(&config_file, "auth_port")?,
+            game_port: read_port(&config_file, "game_port")?,
+            experimental_game_service: config_file
+                .get_bool("experimental_game_service")
+                .unwrap_or(false),
             rsa_private_key: config_file
                 .get_str("rsa_private_key")
-                .expect("Could not read rsa_private_key from config file"),
-        }
+                .map_err(|_| ConfigError::Missing("rsa_private_key".to_string()))?,
+            max_connections: read_limit(&config_file, "max_connections", 1024)?,
+            max_connections_per_ip: read_limit(&config_file, "max_connections_per_ip", 8)?,
+            accept_rate_per_ip: read_positive_float(&config_file, "accept_rate_per_ip", 1.0)?,
+            accept_burst_per_ip: read_positive_float(&config_file, "accept_burst_per_ip", 10.0)?,
+            login_proxy_protocol: config_file
+                .get_bool("login_proxy_protocol")
+                .unwrap_or(false),
+            auth_proxy_protocol: config_file.get_bool("auth_proxy_protocol").unwrap_or(false),
+            game_proxy_protocol: config_file.get_bool("game_proxy_protocol").unwrap_or(false),
+            blood_message_max_per_area: read_limit(
+                &config_file,
+                "blood_message_max_per_area",
+                512,
+            )?,
+            blood_message_max_per_player: read_limit(
+                &config_file,
+                "blood_message_max_per_player",
+                10,
+            )?,
+            blood_message_list_max: read_limit(&config_file, "blood_message_list_max", 64)?,
+            bloodstain_max_per_area: read_limit(&config_file, "bloodstain_max_per_area", 256)?,
+            bloodstain_max_age_mins: read_limit(&config_file, "bloodstain_max_age_mins", 1440)?,
+            bloodstain_max_data_size: read_limit(&config_file, "bloodstain_max_data_size", 1024)?,
+            bloodstain_max_replay_size: read_limit(
+                &config_file,
+                "bloodstain_max_replay_size",
+                32768,
+            )?,
+            bloodstain_list_max: read_limit(&config_file, "bloodstain_list_max", 64)?,
+            ghost_max_per_area: read_limit(&config_file, "ghost_max_per_area", 64)?,
+            ghost_memory_budget_mb: read_limit(&config_file, "ghost_memory_budget_mb", 64)?,
+            ghost_max_size: read_limit(&config_file, "ghost_max_size", 16384)?,
+            ghost_list_max: read_limit(&config_file, "ghost_list_max", 16)?,
+            sign_list_max: read_limit(&config_file, "sign_list_max", 32)?,
+            max_phantoms_per_world: read_limit(&config_file, "max_phantoms_per_world", 3)?,
+            break_in_target_list_max: read_limit(&config_file, "break_in_target_list_max", 8)?,
+            matching_rules: MatchingRules::load(&config_file)?,
+            covenants: CovenantSettings::load(&config_file)?,
+            data_dir: PathBuf::from(
+                config_file
+                    .get_str("data_dir")
+                    .unwrap_or_else(|_| "data".to_string()),
+            ),
+            arena_rating_initial: config_file
+                .get_float("arena_rating_initial")
+                .unwrap_or(1500.0),
+            arena_rating_k_factor: config_file
+                .get_float("arena_rating_k_factor")
+                .unwrap_or(32.0),
+            arena_rating_window: config_file
+                .get_float("arena_rating_window")
+                .unwrap_or(100.0),
+            arena_rating_window_growth_per_min: config_file
+                .get_float("arena_rating_window_growth_per_min")
+                .unwrap_or(50.0),
+            arena_rating_window_max: config_file
+                .get_float("arena_rating_window_max")
+                .unwrap_or(400.0),
+            arena_ranking_list_max: read_limit(&config_file, "arena_ranking_list_max", 100)?,
+            ranking_list_max: read_limit(&config_file, "ranking_list_max", 100)?,
+            ranking_board_count: read_limit(&config_file, "ranking_board_count", 64)?,
+            ranking_max_data_size: read_limit(&config_file, "ranking_max_data_size", 256)?,
+            character_slot_count: read_limit(&config_file, "character_slot_count", 10)?,
+            character_max_data_size: read_limit(&config_file, "character_max_data_size", 65536)?,
+            announcements_file: PathBuf::from(
+                config_file
+                    .get_str("announcements_file")
+                    .unwrap_or_else(|_| "Announcements.toml".to_string()),
+            ),
+        })
     }
 
     pub fn get_server_ip(&self) -> &String {
@@ -60,9 +244,74 @@ impl Config {
         self.game_port
     }
 
+    /// Whether to run the game service, which isn't compatible with the real client yet.
+    pub fn is_game_service_enabled(&self) -> bool {
+        self.experimental_game_service
+    }
+
     pub fn get_rsa_private_key(&self) -> &String {
         &self.rsa_private_key
     }
+
+    pub fn bloodstain_limits(&self) -> BloodstainLimits {
+        BloodstainLimits {
+            max_per_area: self.bloodstain_max_per_area,
+            max_age: chrono::Duration::minutes(self.bloodstain_max_age_mins),
+            max_data_size: self.bloodstain_max_data_size,
+            max_replay_size: self.bloodstain_max_replay_size,
+        }
+    }
+
+    pub fn ranking_limits(&self) -> RankingLimits {
+        RankingLimits {
+            board_count: self.ranking_board_count,
+            max_data_size: self.ranking_max_data_size,
+        }
+    }
+
+    pub fn character_limits(&self) -> CharacterLimits {
+        CharacterLimits {
+            slot_count: self.character_slot_count,
+            max_data_size: self.character_max_data_size,
+        }
+    }
+
+    pub fn ghost_limits(&self) -> GhostLimits {
+        GhostLimits {
+            max_per_area: self.ghost_max_per_area,
+            memory_budget: self.ghost_memory_budget_mb * 1024 * 1024,
+            max_size: self.ghost_max_size,
+        }
+    }
+
+    /// Where the data file called `name` lives.
+    pub fn data_path(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    pub fn elo_settings(&self) -> EloSettings {
+        EloSettings {
+            initial_rating: self.arena_rating_initial,
+            k_factor: self.arena_rating_k_factor,
+        }
+    }
+
+    pub fn rating_window(&self) -> RatingWindow {
+        RatingWindow {
+            initial: self.arena_rating_window,
+            growth_per_min: self.arena_rating_window_growth_per_min,
+            max: self.arena_rating_window_max,
+        }
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            accept_rate_per_ip: self.accept_rate_per_ip,
+            accept_burst_per_ip: self.accept_burst_per_ip,
+        }
+    }
 }
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -87,13 +336,59 @@ async fn main() -> Result<()> {
     // Read config settings from the "Settings.toml" file
     let mut settings = config::Config::default();
     settings.merge(config::File::with_name("Settings")).unwrap();
-    let config = Config::new(settings);
+    let config = Config::new(settings)?;
     let db = MatchmakingDb::new(config);
+    db.spawn_background_tasks();
 
     let mut auth_service = service::auth::create_auth_service(&db);
     let mut login_service = service::login::create_login_service(&db);
+    let mut game_service = if db.config().is_game_service_enabled() {
+        warn!("The game service is experimental and can't talk to the real client yet");
+        service::game::spawn_session_cleanup(&db);
+
+        Some(service::game::create_game_service(&db))
+    } else {
+        None
+    };
+
+    let mut metrics = vec![
+        ("login", login_service.metrics().clone()),
+        ("auth", auth_service.metrics().clone()),
+    ];
+    if let Some(game_service) = &game_service {
+        metrics.push(("game", game_service.metrics().clone()));
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            for (service, metrics) in &metrics {
+                let snapshot = metrics.snapshot();
+
+                info!(
+                    service,
+                    accepted = snapshot.accepted,
+                    rejected_global_limit = snapshot.rejected_global_limit,
+                    rejected_per_ip_limit = snapshot.rejected_per_ip_limit,
+                    rejected_rate_limited = snapshot.rejected_rate_limited,
+                    invalid_proxy_header = snapshot.invalid_proxy_header,
+                    "Connection metrics"
+                );
+            }
+        }
+    });
+
+    let run_game_service = async {
+        match &mut game_service {
+            Some(game_service) => game_service.run().await,
+            None => Ok(()),
+        }
+    };
 
-    tokio::try_join!(login_service.run(), auth_service.run())?;
+    tokio::try_join!(login_service.run(), auth_service.run(), run_game_service)?;
 
     Ok(())
 }
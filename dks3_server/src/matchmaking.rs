@@ -0,0 +1,16 @@
+pub mod announcement;
+pub mod arena_rating;
+pub mod blood_message;
+pub mod bloodstain;
+pub mod boss_defender;
+pub mod character;
+pub mod covenant;
+pub mod ghost;
+pub mod invasion;
+pub mod player_status;
+pub mod quick_match;
+pub mod ranking;
+pub mod rules;
+pub mod session;
+pub mod sign;
+pub mod world;
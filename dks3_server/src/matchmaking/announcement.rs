@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+/// How often the announcements file is checked for changes.
+const RELOAD_INTERVAL_SECS: u64 = 10;
+
+#[derive(Debug, Error)]
+pub enum AnnouncementError {
+    #[error("unable to read announcements: {0}")]
+    Config(#[from] config::ConfigError),
+
+    #[error("announcement {header:?} has an invalid {field} {value:?}, expected RFC 3339")]
+    InvalidDate {
+        header: String,
+        field: &'static str,
+        value: String,
+    },
+}
+
+/// An announcement as written in the announcements file, with dates as RFC 3339 strings.
+#[derive(Deserialize)]
+struct AnnouncementEntry {
+    header: String,
+    message: String,
+    date: String,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnnouncementFile {
+    #[serde(default)]
+    changes: Vec<AnnouncementEntry>,
+    #[serde(default)]
+    notices: Vec<AnnouncementEntry>,
+}
+
+/// A message shown to players on the title screen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Announcement {
+    pub header: String,
+    pub message: String,
+    /// The date shown with the announcement.
+    pub date: DateTime<Utc>,
+    /// When to start showing the announcement, or None to show it straight away.
+    pub start: Option<DateTime<Utc>>,
+    /// When to stop showing the announcement, or None to show it until it's removed.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl Announcement {
+    fn parse(entry: AnnouncementEntry) -> Result<Self, AnnouncementError> {
+        let parse_date = |field: &'static str, value: String| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|date| date.with_timezone(&Utc))
+                .map_err(|_| AnnouncementError::InvalidDate {
+                    header: entry.header.clone(),
+                    field,
+                    value,
+                })
+        };
+
+        Ok(Self {
+            date: parse_date("date", entry.date.clone())?,
+            start: entry
+                .start
+                .clone()
+                .map(|start| parse_date("start", start))
+                .transpose()?,
+            end: entry
+                .end
+                .clone()
+                .map(|end| parse_date("end", end))
+                .transpose()?,
+            header: entry.header,
+            message: entry.message,
+        })
+    }
+
+    /// Whether the announcement should be shown at `now`.
+    pub fn is_showing(&self, now: DateTime<Utc>) -> bool {
+        self.start.map_or(true, |start| start <= now) && self.end.map_or(true, |end| now < end)
+    }
+}
+
+/// The announcements, split the way the client shows them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Announcements {
+    /// Changes to the game or the server.
+    pub changes: Vec<Announcement>,
+    pub notices: Vec<Announcement>,
+}
+
+impl Announcements {
+    fn read(path: &Path) -> Result<Self, AnnouncementError> {
+        let mut config_file = config::Config::default();
+        config_file.merge(config::File::from(path).required(false))?;
+
+        let file: AnnouncementFile = config_file.try_into()?;
+        let parse_all = |entries: Vec<AnnouncementEntry>| {
+            entries
+                .into_iter()
+                .map(Announcement::parse)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            changes: parse_all(file.changes)?,
+            notices: parse_all(file.notices)?,
+        })
+    }
+
+    fn showing(&self, now: DateTime<Utc>) -> Self {
+        let showing = |announcements: &[Announcement]| {
+            announcements
+                .iter()
+                .filter(|announcement| announcement.is_showing(now))
+                .cloned()
+                .collect()
+        };
+
+        Self {
+            changes: showing(&self.changes),
+            notices: showing(&self.notices),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AnnouncementState {
+    /// When the file was last modified as of the last read, or None if it didn't exist.
+    modified: Option<SystemTime>,
+    announcements: Announcements,
+}
+
+/// The announcements file, read again when it changes so announcements can be edited without a
+/// restart.
+#[derive(Clone, Debug)]
+pub struct AnnouncementStore {
+    path: PathBuf,
+    state: Arc<RwLock<AnnouncementState>>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl AnnouncementStore {
+    /// Read the announcements file at `path`. A missing file means there are no announcements.
+    pub fn load(path: PathBuf) -> Result<Self, AnnouncementError> {
+        let state = AnnouncementState {
+            modified: modified(&path),
+            announcements: Announcements::read(&path)?,
+        };
+
+        Ok(Self {
+            path,
+            state: Arc::new(RwLock::new(state)),
+        })
+    }
+
+    /// Read the file again if it has changed since it was last read. If it can't be read the
+    /// previous announcements are kept and the error is returned. Returns whether it was read.
+    /// This reads from disk, so it shouldn't be called from an async task.
+    fn reload_if_changed(&self) -> Result<bool, AnnouncementError> {
+        let modified = modified(&self.path);
+
+        if self.state.read().modified == modified {
+            return Ok(false);
+        }
+
+        let announcements = Announcements::read(&self.path);
+        let mut state = self.state.write();
+
+        // Remember the change even if the file is broken, so it isn't read again every time
+        state.modified = modified;
+        state.announcements = announcements?;
+
+        Ok(true)
+    }
+
+    /// Start checking the file for changes in the background, reading it again when it has.
+    pub fn spawn_reloader(&self) {
+        let store = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(RELOAD_INTERVAL_SECS));
+
+            loop {
+                interval.tick().await;
+
+                let reloader = store.clone();
+                match tokio::task::spawn_blocking(move || reloader.reload_if_changed()).await {
+                    Ok(Ok(true)) => info!("Reloaded announcements"),
+                    Ok(Ok(false)) => {}
+                    Ok(Err(e)) => {
+                        warn!(error = %e, "Unable to reload announcements, keeping the old ones")
+                    }
+                    Err(e) => error!(error = %e, "Announcement reload failed"),
+                }
+            }
+        });
+    }
+
+    /// The announcements that should be shown at `now`. Those outside their display window are
+    /// left out.
+    pub fn showing(&self, now: DateTime<Utc>) -> Announcements {
+        self.state.read().announcements.showing(now)
+    }
+}
@@ -0,0 +1,433 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::matchmaking::quick_match::{QuickMatch, QuickMatchMode};
+use crate::matchmaking::session::SteamId;
+use crate::storage::{self, Dirty, StorageError};
+
+/// How arena ratings move, using the Elo system.
+#[derive(Clone, Copy, Debug)]
+pub struct EloSettings {
+    /// The rating a player starts each mode on.
+    pub initial_rating: f64,
+    /// The most a rating can move after one match.
+    pub k_factor: f64,
+}
+
+/// The chance of a player rated `rating` beating one rated `opponent`, under Elo.
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+/// A player's standing in one arena mode.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArenaRating {
+    pub rating: f64,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl ArenaRating {
+    fn new(initial_rating: f64) -> Self {
+        Self {
+            rating: initial_rating,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+        }
+    }
+
+    pub fn matches(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+}
+
+/// How a rating is kept in the data file.
+#[derive(Serialize, Deserialize)]
+struct RatingRecord {
+    steam_id: u64,
+    mode: u32,
+    rating: ArenaRating,
+}
+
+/// Where one player stands against another on a leaderboard. Higher ratings come first, then
+/// whoever has played more, then the lower steamid, so no two players ever share a place.
+fn compare_standing(a: &(SteamId, ArenaRating), b: &(SteamId, ArenaRating)) -> Ordering {
+    b.1.rating
+        .partial_cmp(&a.1.rating)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| b.1.matches().cmp(&a.1.matches()))
+        .then_with(|| a.0.cmp(&b.0))
+}
+
+/// The ratings in one mode, by player and kept sorted best first.
+#[derive(Debug, Default)]
+struct ModeRatings {
+    players: HashMap<SteamId, ArenaRating>,
+    ranked: Vec<(SteamId, ArenaRating)>,
+}
+
+impl ModeRatings {
+    fn set(&mut self, steam_id: SteamId, rating: ArenaRating) {
+        if let Some(previous) = self.players.insert(steam_id, rating) {
+            let previous = (steam_id, previous);
+
+            if let Ok(index) = self
+                .ranked
+                .binary_search_by(|other| compare_standing(other, &previous))
+            {
+                self.ranked.remove(index);
+            }
+        }
+
+        let entry = (steam_id, rating);
+        let index = match self
+            .ranked
+            .binary_search_by(|other| compare_standing(other, &entry))
+        {
+            Ok(index) | Err(index) => index,
+        };
+
+        self.ranked.insert(index, entry);
+    }
+}
+
+type Ratings = HashMap<QuickMatchMode, ModeRatings>;
+
+/// Every player's rating in each arena mode, updated from match results and kept on disk.
+#[derive(Clone, Debug)]
+pub struct ArenaRatingStore {
+    settings: EloSettings,
+    path: PathBuf,
+    ratings: Arc<RwLock<Ratings>>,
+    dirty: Dirty,
+}
+
+impl ArenaRatingStore {
+    /// Load the ratings stored at `path`, starting afresh if there are none yet.
+    pub fn load(settings: EloSettings, path: PathBuf) -> Result<Self, StorageError> {
+        let mut ratings = Ratings::new();
+
+        for record in storage::load::<Vec<RatingRecord>>(&path)?.unwrap_or_default() {
+            if let Ok(mode) = QuickMatchMode::try_from(record.mode) {
+                ratings
+                    .entry(mode)
+                    .or_default()
+                    .set(SteamId::new(record.steam_id), record.rating);
+            }
+        }
+
+        Ok(Self {
+            settings,
+            path,
+            ratings: Arc::new(RwLock::new(ratings)),
+            dirty: Dirty::default(),
+        })
+    }
+
+    /// Start storing the ratings in the background whenever they change.
+    pub fn spawn_writer(&self) {
+        let store = self.clone();
+
+        storage::spawn_writer(self.path.clone(), self.dirty.clone(), move || {
+            store.records()
+        });
+    }
+
+    fn records(&self) -> Vec<RatingRecord> {
+        self.ratings
+            .read()
+            .iter()
+            .flat_map(|(mode, ratings)| {
+                ratings
+                    .ranked
+                    .iter()
+                    .map(move |(steam_id, rating)| RatingRecord {
+                        steam_id: steam_id.as_u64(),
+                        mode: (*mode).into(),
+                        rating: *rating,
+                    })
+            })
+            .collect()
+    }
+
+    /// The player's rating in `mode`, which is the initial rating if they haven't played it.
+    pub fn get(&self, steam_id: SteamId, mode: QuickMatchMode) -> ArenaRating {
+        self.ratings
+            .read()
+            .get(&mode)
+            .and_then(|ratings| ratings.players.get(&steam_id))
+            .copied()
+            .unwrap_or_else(|| ArenaRating::new(self.settings.initial_rating))
+    }
+
+    /// Every mode the player has played and their rating in it, for admin tools.
+    pub fn player_ratings(&self, steam_id: SteamId) -> Vec<(QuickMatchMode, ArenaRating)> {
+        self.ratings
+            .read()
+            .iter()
+            .filter_map(|(mode, ratings)| Some((*mode, *ratings.players.get(&steam_id)?)))
+            .collect()
+    }
+
+    /// Replace the player's rating in `mode`, for admins correcting one.
+    pub fn correct(&self, steam_id: SteamId, mode: QuickMatchMode, rating: ArenaRating) {
+        self.ratings
+            .write()
+            .entry(mode)
+            .or_default()
+            .set(steam_id, rating);
+        self.dirty.mark();
+    }
+
+    /// Update the ratings of everyone in a finished match from its outcome. Only matches whose
+    /// players agreed on the result have one, so a disputed match leaves ratings alone.
+    ///
+    /// Each player is scored against every player on another team and moves by the average of
+    /// those results, so team and free for all matches move ratings as much as duels do. In a free
+    /// for all the losers aren't scored against each other.
+    pub fn record(&self, quick_match: &QuickMatch) {
+        let winning_team = match quick_match.outcome {
+            Some(outcome) => outcome.winning_team,
+            None => return,
+        };
+
+        let mut ratings = self.ratings.write();
+        let initial_rating = self.settings.initial_rating;
+        let mode_ratings = ratings.entry(quick_match.mode).or_default();
+
+        let before: Vec<_> = quick_match
+            .players
+            .iter()
+            .map(|player| {
+                let rating = mode_ratings
+                    .players
+                    .get(&player.steam_id)
+                    .copied()
+                    .unwrap_or_else(|| ArenaRating::new(initial_rating));
+
+                (player, rating)
+            })
+            .collect();
+
+        for &(player, rating) in &before {
+            let mut total = 0.0;
+            let mut opponents = 0;
+
+            for &(opponent, opponent_rating) in &before {
+                if opponent.team == player.team {
+                    continue;
+                }
+
+                let score = match winning_team {
+                    None => 0.5,
+                    Some(team) if team == player.team => 1.0,
+                    Some(team) if team == opponent.team => 0.0,
+                    Some(_) => continue,
+                };
+
+                total += score - expected_score(rating.rating, opponent_rating.rating);
+                opponents += 1;
+            }
+
+            let mut updated = rating;
+
+            if opponents > 0 {
+                updated.rating = rating.rating + self.settings.k_factor * total / opponents as f64;
+            }
+
+            match winning_team {
+                None => updated.draws += 1,
+                Some(team) if team == player.team => updated.wins += 1,
+                Some(_) => updated.losses += 1,
+            }
+
+            mode_ratings.set(player.steam_id, updated);
+        }
+
+        self.dirty.mark();
+    }
+
+    /// Up to `count` players in `mode` from the `offset`th place down, best first.
+    pub fn leaderboard(
+        &self,
+        mode: QuickMatchMode,
+        offset: usize,
+        count: usize,
+    ) -> Vec<(SteamId, ArenaRating)> {
+        self.ratings
+            .read()
+            .get(&mode)
+            .map(|ratings| {
+                ratings
+                    .ranked
+                    .iter()
+                    .skip(offset)
+                    .take(count)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::matchmaking::quick_match::{QuickMatchOutcome, QuickMatchPlayer};
+
+    const SETTINGS: EloSettings = EloSettings {
+        initial_rating: 1500.0,
+        k_factor: 32.0,
+    };
+
+    fn store() -> ArenaRatingStore {
+        // Nothing is written unless the writer is started, so the file never exists
+        let path = std::env::temp_dir().join("dks3-arena-ratings-test.json");
+
+        ArenaRatingStore::load(SETTINGS, path).unwrap()
+    }
+
+    fn finished(mode: QuickMatchMode, teams: &[u32], winning_team: Option<u32>) -> QuickMatch {
+        QuickMatch {
+            id: 1,
+            mode,
+            map_id: 0,
+            host: SteamId::new(1),
+            players: teams
+                .iter()
+                .enumerate()
+                .map(|(index, &team)| QuickMatchPlayer {
+                    steam_id: SteamId::new(index as u64 + 1),
+                    team,
+                })
+                .collect(),
+            formed_at: Utc::now(),
+            reports: vec![],
+            outcome: Some(QuickMatchOutcome {
+                winning_team,
+                decided_at: Utc::now(),
+            }),
+        }
+    }
+
+    fn rating(rating: f64, wins: u32) -> ArenaRating {
+        ArenaRating {
+            rating,
+            wins,
+            losses: 0,
+            draws: 0,
+        }
+    }
+
+    #[test]
+    fn evenly_rated_duel_moves_half_the_k_factor() {
+        let store = store();
+        store.record(&finished(QuickMatchMode::Duel, &[0, 1], Some(0)));
+
+        let winner = store.get(SteamId::new(1), QuickMatchMode::Duel);
+        let loser = store.get(SteamId::new(2), QuickMatchMode::Duel);
+
+        assert_eq!(winner.rating, 1516.0);
+        assert_eq!((winner.wins, winner.losses), (1, 0));
+        assert_eq!(loser.rating, 1484.0);
+        assert_eq!((loser.wins, loser.losses), (0, 1));
+    }
+
+    #[test]
+    fn upset_moves_ratings_more_than_expected_win() {
+        let store = store();
+        store.correct(SteamId::new(1), QuickMatchMode::Duel, rating(1300.0, 0));
+        store.correct(SteamId::new(2), QuickMatchMode::Duel, rating(1700.0, 0));
+        store.record(&finished(QuickMatchMode::Duel, &[0, 1], Some(0)));
+
+        // The favourite was expected to win 10 times out of 11
+        let expected_gain = 32.0 * (1.0 - 1.0 / 11.0);
+        let underdog = store.get(SteamId::new(1), QuickMatchMode::Duel);
+        let favourite = store.get(SteamId::new(2), QuickMatchMode::Duel);
+
+        assert!((underdog.rating - (1300.0 + expected_gain)).abs() < 1e-9);
+        assert!((favourite.rating - (1700.0 - expected_gain)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn even_draw_only_counts_the_match() {
+        let store = store();
+        store.record(&finished(QuickMatchMode::TwoVsTwo, &[0, 0, 1, 1], None));
+
+        for id in 1..=4 {
+            let player = store.get(SteamId::new(id), QuickMatchMode::TwoVsTwo);
+
+            assert_eq!(player.rating, 1500.0);
+            assert_eq!(player.draws, 1);
+        }
+    }
+
+    #[test]
+    fn undecided_match_leaves_ratings_alone() {
+        let store = store();
+        let mut quick_match = finished(QuickMatchMode::Duel, &[0, 1], Some(0));
+        quick_match.outcome = None;
+        store.record(&quick_match);
+
+        assert!(store.player_ratings(SteamId::new(1)).is_empty());
+        assert_eq!(
+            store.get(SteamId::new(1), QuickMatchMode::Duel).matches(),
+            0
+        );
+    }
+
+    #[test]
+    fn free_for_all_losers_are_not_scored_against_each_other() {
+        let store = store();
+        store.record(&finished(QuickMatchMode::FreeForAll, &[0, 1, 2], Some(0)));
+
+        let ratings: Vec<_> = (1..=3)
+            .map(|id| {
+                store
+                    .get(SteamId::new(id), QuickMatchMode::FreeForAll)
+                    .rating
+            })
+            .collect();
+
+        assert_eq!(ratings, vec![1516.0, 1484.0, 1484.0]);
+    }
+
+    #[test]
+    fn leaderboard_stays_ordered_with_stable_ties() {
+        let store = store();
+        let mode = QuickMatchMode::Duel;
+        store.correct(SteamId::new(3), mode, rating(1500.0, 2));
+        store.correct(SteamId::new(2), mode, rating(1500.0, 2));
+        store.correct(SteamId::new(1), mode, rating(1500.0, 1));
+        store.correct(SteamId::new(4), mode, rating(1600.0, 0));
+
+        let order = |offset, count| -> Vec<u64> {
+            store
+                .leaderboard(mode, offset, count)
+                .into_iter()
+                .map(|(steam_id, _)| steam_id.as_u64())
+                .collect()
+        };
+
+        assert_eq!(order(0, 10), vec![4, 2, 3, 1]);
+        assert_eq!(order(1, 2), vec![2, 3]);
+
+        // Moving a player takes them out of their old place
+        store.correct(SteamId::new(4), mode, rating(1400.0, 0));
+        assert_eq!(order(0, 10), vec![2, 3, 1, 4]);
+        assert_eq!(
+            store.player_ratings(SteamId::new(4)),
+            vec![(mode, rating(1400.0, 0))]
+        );
+    }
+}
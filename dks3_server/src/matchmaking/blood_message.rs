@@ -0,0 +1,461 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::matchmaking::session::SteamId;
+use crate::storage::{self, Dirty, StorageError};
+
+/// The words and gesture a player picked when writing a message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloodMessageContents {
+    pub template_id: u32,
+    pub word_id: u32,
+    pub conjunction_id: Option<u32>,
+    pub second_template_id: Option<u32>,
+    pub second_word_id: Option<u32>,
+    pub gesture_id: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct BloodMessage {
+    pub id: u64,
+    pub author: SteamId,
+    pub character_id: u32,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    pub contents: BloodMessageContents,
+    /// Where the message sits in the world, which only the client understands.
+    pub placement: Bytes,
+    pub created_at: DateTime<Utc>,
+    pub good: u32,
+    pub poor: u32,
+    evaluated_by: HashSet<SteamId>,
+}
+
+impl BloodMessage {
+    /// How likely this message is to be picked when listing an area, relative to its neighbours.
+    /// Well rated messages are favoured, as are newer messages so that an area's messages turn
+    /// over.
+    pub fn quality_weight(&self, now: DateTime<Utc>) -> f64 {
+        const HALF_LIFE_DAYS: f64 = 7.0;
+
+        let age_days = (now - self.created_at).num_seconds().max(0) as f64 / 86_400.0;
+        let recency = 0.25 + 0.75 * 0.5f64.powf(age_days / HALF_LIFE_DAYS);
+
+        // The share of good ratings with one good and one poor rating assumed up front, so an
+        // unrated message sits in the middle at 1.0 and a couple of ratings can't dominate.
+        let rating = 2.0 * (self.good as f64 + 1.0) / ((self.good + self.poor) as f64 + 2.0);
+
+        recency * rating
+    }
+}
+
+/// How a message is kept in the data file.
+#[derive(Serialize, Deserialize)]
+struct BloodMessageRecord {
+    id: u64,
+    author: u64,
+    character_id: u32,
+    online_area_id: u32,
+    cell_id: u32,
+    contents: BloodMessageContents,
+    placement: Vec<u8>,
+    /// In milliseconds since the epoch.
+    created_at: i64,
+    good: u32,
+    poor: u32,
+    evaluated_by: Vec<u64>,
+}
+
+impl From<&BloodMessage> for BloodMessageRecord {
+    fn from(message: &BloodMessage) -> Self {
+        Self {
+            id: message.id,
+            author: message.author.as_u64(),
+            character_id: message.character_id,
+            online_area_id: message.online_area_id,
+            cell_id: message.cell_id,
+            contents: message.contents.clone(),
+            placement: message.placement.to_vec(),
+            created_at: message.created_at.timestamp_millis(),
+            good: message.good,
+            poor: message.poor,
+            evaluated_by: message
+                .evaluated_by
+                .iter()
+                .map(|steam_id| steam_id.as_u64())
+                .collect(),
+        }
+    }
+}
+
+impl From<BloodMessageRecord> for BloodMessage {
+    fn from(record: BloodMessageRecord) -> Self {
+        Self {
+            id: record.id,
+            author: SteamId::new(record.author),
+            character_id: record.character_id,
+            online_area_id: record.online_area_id,
+            cell_id: record.cell_id,
+            contents: record.contents,
+            placement: Bytes::from(record.placement),
+            created_at: Utc.timestamp_millis(record.created_at),
+            good: record.good,
+            poor: record.poor,
+            evaluated_by: record.evaluated_by.into_iter().map(SteamId::new).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum EvaluateError {
+    #[error("message does not exist")]
+    NotFound,
+
+    #[error("players can't rate their own messages")]
+    OwnMessage,
+
+    #[error("player has already rated this message")]
+    AlreadyEvaluated,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewBloodMessage {
+    pub author: SteamId,
+    pub character_id: u32,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    pub contents: BloodMessageContents,
+    pub placement: Bytes,
+}
+
+#[derive(Default, Debug)]
+struct BloodMessageState {
+    next_id: u64,
+    messages: HashMap<u64, BloodMessage>,
+    /// Message IDs in each online area, oldest first.
+    areas: HashMap<u32, VecDeque<u64>>,
+    /// Message IDs written by each player, oldest first.
+    authors: HashMap<SteamId, VecDeque<u64>>,
+}
+
+impl BloodMessageState {
+    /// Store a message, making room for it if needed. Returns None if the limits leave no room for
+    /// it at all.
+    fn insert(
+        &mut self,
+        message: NewBloodMessage,
+        max_per_area: usize,
+        max_per_player: usize,
+    ) -> Option<u64> {
+        if max_per_area == 0 || max_per_player == 0 {
+            return None;
+        }
+
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let message = BloodMessage {
+            id,
+            author: message.author,
+            character_id: message.character_id,
+            online_area_id: message.online_area_id,
+            cell_id: message.cell_id,
+            contents: message.contents,
+            placement: message.placement,
+            created_at: Utc::now(),
+            good: 0,
+            poor: 0,
+            evaluated_by: HashSet::new(),
+        };
+        self.place(message, max_per_area, max_per_player);
+
+        Some(id)
+    }
+
+    /// Add a message as the newest in its area and by its author, removing the oldest of either
+    /// if that takes them over their limit.
+    fn place(&mut self, message: BloodMessage, max_per_area: usize, max_per_player: usize) {
+        let id = message.id;
+        let author = message.author;
+        let area_id = message.online_area_id;
+
+        self.messages.insert(id, message);
+
+        let area = self.areas.entry(area_id).or_default();
+        area.push_back(id);
+        let evicted_from_area = if area.len() > max_per_area {
+            area.front().copied()
+        } else {
+            None
+        };
+
+        let authored = self.authors.entry(author).or_default();
+        authored.push_back(id);
+        let evicted_from_author = if authored.len() > max_per_player {
+            authored.front().copied()
+        } else {
+            None
+        };
+
+        for evicted in evicted_from_area.into_iter().chain(evicted_from_author) {
+            self.remove(evicted);
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Option<BloodMessage> {
+        let message = self.messages.remove(&id)?;
+
+        if let Some(area) = self.areas.get_mut(&message.online_area_id) {
+            area.retain(|&other| other != id);
+
+            if area.is_empty() {
+                self.areas.remove(&message.online_area_id);
+            }
+        }
+
+        if let Some(authored) = self.authors.get_mut(&message.author) {
+            authored.retain(|&other| other != id);
+
+            if authored.is_empty() {
+                self.authors.remove(&message.author);
+            }
+        }
+
+        Some(message)
+    }
+
+    /// Find a message `author` has already placed that is the same as `message`.
+    fn find_duplicate(&self, author: SteamId, message: &NewBloodMessage) -> Option<u64> {
+        self.authors.get(&author)?.iter().copied().find(|id| {
+            self.messages.get(id).map_or(false, |existing| {
+                existing.online_area_id == message.online_area_id
+                    && existing.cell_id == message.cell_id
+                    && existing.contents == message.contents
+                    && existing.placement == message.placement
+            })
+        })
+    }
+}
+
+/// A message the client already has placed, sent back to us when the player logs in.
+#[derive(Clone, Debug)]
+pub struct ReenteredBloodMessage {
+    /// The ID we gave the message when it was created, if the client knows it.
+    pub id: Option<u64>,
+    pub message: NewBloodMessage,
+}
+
+/// Player written messages, grouped by the online area they were placed in and kept on disk so
+/// players find their messages still in place after a restart.
+#[derive(Clone, Debug)]
+pub struct BloodMessageStore {
+    max_per_area: usize,
+    max_per_player: usize,
+    path: PathBuf,
+    state: Arc<RwLock<BloodMessageState>>,
+    dirty: Dirty,
+}
+
+impl BloodMessageStore {
+    /// Load the messages stored at `path`, starting afresh if there are none yet. If the limits
+    /// have been lowered since, the oldest messages over them are dropped.
+    pub fn load(
+        max_per_area: usize,
+        max_per_player: usize,
+        path: PathBuf,
+    ) -> Result<Self, StorageError> {
+        let mut records: Vec<BloodMessageRecord> = storage::load(&path)?.unwrap_or_default();
+        records.sort_by_key(|record| record.id);
+
+        let mut state = BloodMessageState::default();
+
+        if max_per_area > 0 && max_per_player > 0 {
+            for record in records {
+                state.next_id = state.next_id.max(record.id);
+                state.place(record.into(), max_per_area, max_per_player);
+            }
+        }
+
+        Ok(Self {
+            max_per_area,
+            max_per_player,
+            path,
+            state: Arc::new(RwLock::new(state)),
+            dirty: Dirty::default(),
+        })
+    }
+
+    /// Start storing the messages in the background whenever they change.
+    pub fn spawn_writer(&self) {
+        let store = self.clone();
+
+        storage::spawn_writer(self.path.clone(), self.dirty.clone(), move || {
+            store.records()
+        });
+    }
+
+    fn records(&self) -> Vec<BloodMessageRecord> {
+        self.state
+            .read()
+            .messages
+            .values()
+            .map(BloodMessageRecord::from)
+            .collect()
+    }
+
+    /// Store a new message. If the area is full, or the author has reached their limit, the oldest
+    /// message in the area or by the author is removed to make room. Returns None if the limits
+    /// don't allow any messages.
+    pub fn create(&self, message: NewBloodMessage) -> Option<u64> {
+        let id = self
+            .state
+            .write()
+            .insert(message, self.max_per_area, self.max_per_player);
+
+        self.dirty.mark();
+        id
+    }
+
+    /// Put the messages in `messages` back in the world, returning the IDs of those that are in it
+    /// afterwards in the same order. Messages we still hold, either by ID or by being identical to
+    /// one the author has placed, are kept as they are rather than being created again. Only the
+    /// newest messages up to the author's limit are kept, and any pushed out by others in the
+    /// batch are left out.
+    pub fn reenter(&self, author: SteamId, messages: Vec<ReenteredBloodMessage>) -> Vec<u64> {
+        let mut state = self.state.write();
+        let skip = messages.len().saturating_sub(self.max_per_player);
+
+        let ids: Vec<u64> = messages
+            .into_iter()
+            .skip(skip)
+            .filter_map(|reentered| {
+                let message = reentered.message;
+                let known = reentered
+                    .id
+                    .filter(|id| state.messages.get(id).map_or(false, |m| m.author == author));
+
+                match known.or_else(|| state.find_duplicate(author, &message)) {
+                    Some(id) => Some(id),
+                    None => state.insert(
+                        NewBloodMessage { author, ..message },
+                        self.max_per_area,
+                        self.max_per_player,
+                    ),
+                }
+            })
+            .collect();
+
+        self.dirty.mark();
+
+        ids.into_iter()
+            .filter(|id| state.messages.contains_key(id))
+            .collect()
+    }
+
+    /// Remove a message on behalf of `author`. Players can only remove their own messages.
+    pub fn remove(&self, author: SteamId, id: u64) -> bool {
+        let mut state = self.state.write();
+
+        match state.messages.get(&id) {
+            Some(message) if message.author == author => {
+                state.remove(id);
+                self.dirty.mark();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record `evaluator`'s rating of a message, returning the message as it stands afterwards.
+    /// Each player may rate a message once.
+    pub fn evaluate(
+        &self,
+        evaluator: SteamId,
+        id: u64,
+        poor: bool,
+    ) -> Result<BloodMessage, EvaluateError> {
+        let mut state = self.state.write();
+        let message = state.messages.get_mut(&id).ok_or(EvaluateError::NotFound)?;
+
+        if message.author == evaluator {
+            return Err(EvaluateError::OwnMessage);
+        }
+
+        if !message.evaluated_by.insert(evaluator) {
+            return Err(EvaluateError::AlreadyEvaluated);
+        }
+
+        if poor {
+            message.poor += 1;
+        } else {
+            message.good += 1;
+        }
+
+        self.dirty.mark();
+
+        Ok(message.clone())
+    }
+
+    pub fn get(&self, id: u64) -> Option<BloodMessage> {
+        self.state.read().messages.get(&id).cloned()
+    }
+
+    /// Pick up to `count` messages from `online_area_id` for `requester` to see, at random but
+    /// weighted by [BloodMessage::quality_weight]. The requester's own messages are left out, as
+    /// their client already has them.
+    pub fn list<R: Rng>(
+        &self,
+        requester: SteamId,
+        online_area_id: u32,
+        count: usize,
+        rng: &mut R,
+    ) -> Vec<BloodMessage> {
+        let now = Utc::now();
+        let state = self.state.read();
+
+        let candidates = state
+            .areas
+            .get(&online_area_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.messages.get(id))
+            .filter(|message| message.author != requester)
+            .map(|message| (message, message.quality_weight(now)));
+
+        weighted_sample(candidates, count, rng)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Weighted random sampling without replacement (Efraimidis-Spirakis): every item is given the key
+/// `u^(1/w)` for a uniform `u` and the items with the largest keys are kept.
+pub(crate) fn weighted_sample<T, I, R>(items: I, count: usize, rng: &mut R) -> Vec<T>
+where
+    I: IntoIterator<Item = (T, f64)>,
+    R: Rng,
+{
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let mut keyed: Vec<(f64, T)> = items
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0.0)
+        .map(|(item, weight)| (rng.gen::<f64>().powf(1.0 / weight), item))
+        .collect();
+
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    keyed.truncate(count);
+
+    keyed.into_iter().map(|(_, item)| item).collect()
+}
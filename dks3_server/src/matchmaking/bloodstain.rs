@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::matchmaking::session::SteamId;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BloodstainLimits {
+    pub max_per_area: usize,
+    pub max_age: Duration,
+    /// Upper bound on the position data sent with a bloodstain. It only describes where the player
+    /// died, so it is small for a real client.
+    pub max_data_size: usize,
+    pub max_replay_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum BloodstainError {
+    #[error("bloodstain data of {0} bytes is too large")]
+    DataTooLarge(usize),
+
+    #[error("death replay of {0} bytes is too large")]
+    ReplayTooLarge(usize),
+}
+
+/// Left behind where a player died. Touching it plays back their last moments.
+#[derive(Clone, Debug)]
+pub struct Bloodstain {
+    pub id: u64,
+    pub player: SteamId,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    /// Where the player died, which only the client understands.
+    pub data: Bytes,
+    /// The player's movements leading up to their death, also opaque to us.
+    pub replay: Bytes,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewBloodstain {
+    pub player: SteamId,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    pub data: Bytes,
+    pub replay: Bytes,
+}
+
+#[derive(Default, Debug)]
+struct BloodstainState {
+    next_id: u64,
+    bloodstains: HashMap<u64, Bloodstain>,
+    /// Bloodstain IDs in each online area, oldest first.
+    areas: HashMap<u32, VecDeque<u64>>,
+}
+
+impl BloodstainState {
+    /// Drop bloodstains from the front of an area until it is under `max_len` and the oldest
+    /// remaining one was created after `cutoff`.
+    fn trim_area(&mut self, online_area_id: u32, max_len: usize, cutoff: DateTime<Utc>) {
+        let area = match self.areas.get_mut(&online_area_id) {
+            Some(area) => area,
+            None => return,
+        };
+
+        while let Some(&oldest) = area.front() {
+            let expired = self
+                .bloodstains
+                .get(&oldest)
+                .map_or(true, |bloodstain| bloodstain.created_at <= cutoff);
+
+            if area.len() <= max_len && !expired {
+                break;
+            }
+
+            area.pop_front();
+            self.bloodstains.remove(&oldest);
+        }
+
+        if area.is_empty() {
+            self.areas.remove(&online_area_id);
+        }
+    }
+}
+
+/// Bloodstains from recent deaths, grouped by the online area they are in.
+#[derive(Clone, Debug)]
+pub struct BloodstainStore {
+    limits: BloodstainLimits,
+    state: Arc<RwLock<BloodstainState>>,
+}
+
+impl BloodstainStore {
+    pub fn new(limits: BloodstainLimits) -> Self {
+        Self {
+            limits,
+            state: Default::default(),
+        }
+    }
+
+    /// Store a new bloodstain, removing the oldest in the area if it is full along with any that
+    /// have expired.
+    pub fn create(&self, bloodstain: NewBloodstain) -> Result<u64, BloodstainError> {
+        if bloodstain.data.len() > self.limits.max_data_size {
+            return Err(BloodstainError::DataTooLarge(bloodstain.data.len()));
+        }
+
+        if bloodstain.replay.len() > self.limits.max_replay_size {
+            return Err(BloodstainError::ReplayTooLarge(bloodstain.replay.len()));
+        }
+
+        let now = Utc::now();
+        let area_id = bloodstain.online_area_id;
+        let mut state = self.state.write();
+
+        state.next_id += 1;
+        let id = state.next_id;
+
+        state.bloodstains.insert(
+            id,
+            Bloodstain {
+                id,
+                player: bloodstain.player,
+                online_area_id: bloodstain.online_area_id,
+                cell_id: bloodstain.cell_id,
+                data: bloodstain.data,
+                replay: bloodstain.replay,
+                created_at: now,
+            },
+        );
+        state.areas.entry(area_id).or_default().push_back(id);
+        state.trim_area(area_id, self.limits.max_per_area, now - self.limits.max_age);
+
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Bloodstain> {
+        let cutoff = Utc::now() - self.limits.max_age;
+
+        self.state
+            .read()
+            .bloodstains
+            .get(&id)
+            .filter(|bloodstain| bloodstain.created_at > cutoff)
+            .cloned()
+    }
+
+    /// Pick up to `count` unexpired bloodstains from `online_area_id` at random. The requester's
+    /// own bloodstains are left out, as their client already shows them.
+    pub fn list<R: Rng>(
+        &self,
+        requester: SteamId,
+        online_area_id: u32,
+        count: usize,
+        rng: &mut R,
+    ) -> Vec<Bloodstain> {
+        let cutoff = Utc::now() - self.limits.max_age;
+        let state = self.state.read();
+
+        state
+            .areas
+            .get(&online_area_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.bloodstains.get(id))
+            .filter(|bloodstain| bloodstain.player != requester && bloodstain.created_at > cutoff)
+            .choose_multiple(rng, count)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+}
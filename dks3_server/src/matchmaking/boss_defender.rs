@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::matchmaking::rules::{MatchingItem, MatchingParameters, MatchingRules};
+use crate::matchmaking::session::{SessionRegistry, SteamId};
+use crate::matchmaking::world::WorldStore;
+
+/// How long a defender has to answer a summon before they are offered to other hosts again.
+const SUMMON_TIMEOUT_SECS: i64 = 30;
+
+/// A Spear of the Church waiting to be summoned as the boss of another player's world.
+#[derive(Clone, Debug)]
+pub struct BossDefender {
+    pub steam_id: SteamId,
+    pub matching: MatchingParameters,
+    /// The host waiting to hear back from the defender, and when they started waiting.
+    pub summoned_by: Option<(SteamId, DateTime<Utc>)>,
+}
+
+impl BossDefender {
+    /// Whether the defender can be offered to a host, because no one is waiting on them or the
+    /// host waiting on them has given up.
+    fn is_available(&self, now: DateTime<Utc>) -> bool {
+        self.summoned_by.map_or(true, |(_, summoned_at)| {
+            now - summoned_at > Duration::seconds(SUMMON_TIMEOUT_SECS)
+        })
+    }
+}
+
+/// Players registered to defend the boss arena, and the summons in progress on them.
+#[derive(Clone, Debug, Default)]
+pub struct BossDefenderStore {
+    defenders: Arc<RwLock<HashMap<SteamId, BossDefender>>>,
+}
+
+impl BossDefenderStore {
+    /// Register `steam_id` as a defender, or refresh their matching parameters if they already
+    /// are. A summon already in progress on them is kept.
+    pub fn register(&self, steam_id: SteamId, matching: MatchingParameters) {
+        let mut defenders = self.defenders.write();
+        let defender = defenders.entry(steam_id).or_insert_with(|| BossDefender {
+            steam_id,
+            matching: matching.clone(),
+            summoned_by: None,
+        });
+
+        defender.matching = matching;
+    }
+
+    /// Stop `steam_id` being summoned, returning their registration if they had one.
+    pub fn unregister(&self, steam_id: SteamId) -> Option<BossDefender> {
+        self.defenders.write().remove(&steam_id)
+    }
+
+    /// Whether a host is waiting to hear back from `steam_id` about defending their boss.
+    pub fn is_being_summoned(&self, steam_id: SteamId) -> bool {
+        let now = Utc::now();
+
+        self.defenders
+            .read()
+            .get(&steam_id)
+            .map_or(false, |defender| !defender.is_available(now))
+    }
+
+    /// Every registered defender.
+    pub fn players(&self) -> Vec<SteamId> {
+        self.defenders.read().keys().copied().collect()
+    }
+
+    /// Pick a defender at random for `host`, who has entered the boss arena with `matching`. The
+    /// defender has to be online and not already in someone's world, and match the host under the
+    /// boss defender rules. They are held for `host` until they answer. Returns the defender along
+    /// with the host whose summon of them timed out, if this one takes its place.
+    pub fn summon<R: Rng>(
+        &self,
+        sessions: &SessionRegistry,
+        worlds: &WorldStore,
+        rules: &MatchingRules,
+        host: SteamId,
+        matching: &MatchingParameters,
+        rng: &mut R,
+    ) -> Option<(SteamId, Option<SteamId>)> {
+        let now = Utc::now();
+
+        let mut candidates: Vec<SteamId> = self
+            .defenders
+            .read()
+            .values()
+            .filter(|defender| defender.steam_id != host && defender.is_available(now))
+            .filter(|defender| {
+                rules.can_match(MatchingItem::BossDefender, matching, &defender.matching)
+            })
+            .map(|defender| defender.steam_id)
+            .collect();
+
+        // The defenders aren't locked while their sessions and worlds are looked at, since those
+        // stores are locked in the other order elsewhere
+        candidates.retain(|&steam_id| {
+            !worlds.is_in_world(steam_id)
+                && sessions.get(steam_id).map_or(false, |session| {
+                    session.game.is_some()
+                        && session.status.is_alone()
+                        && session.invasion.is_none()
+                })
+        });
+        candidates.shuffle(rng);
+
+        // Someone else may have claimed a candidate in the meantime, so take the first one still
+        // free
+        let mut defenders = self.defenders.write();
+        let steam_id = candidates.into_iter().find(|steam_id| {
+            defenders
+                .get(steam_id)
+                .map_or(false, |defender| defender.is_available(now))
+        })?;
+        let defender = defenders.get_mut(&steam_id)?;
+
+        let timed_out = defender.summoned_by.map(|(host, _)| host);
+        defender.summoned_by = Some((host, now));
+
+        Some((defender.steam_id, timed_out))
+    }
+
+    /// The defender accepted the summon and is on their way to the host's world, so they are no
+    /// longer registered. Returns false if `host` wasn't waiting on them.
+    pub fn accept(&self, steam_id: SteamId, host: SteamId) -> bool {
+        let mut defenders = self.defenders.write();
+
+        match defenders.get(&steam_id) {
+            Some(defender) if defender.summoned_by.map(|(by, _)| by) == Some(host) => {
+                defenders.remove(&steam_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The defender turned the summon down, so they can be offered to other hosts again. Returns
+    /// false if `host` wasn't waiting on them.
+    pub fn reject(&self, steam_id: SteamId, host: SteamId) -> bool {
+        match self.defenders.write().get_mut(&steam_id) {
+            Some(defender) if defender.summoned_by.map(|(by, _)| by) == Some(host) => {
+                defender.summoned_by = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
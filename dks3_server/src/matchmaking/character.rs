@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::error;
+
+use crate::matchmaking::session::SteamId;
+use crate::storage::{self, StorageError};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterLimits {
+    /// Save slots are numbered from 0, so only IDs below this are accepted.
+    pub slot_count: u32,
+    /// Largest character data, in bytes, a client may upload.
+    pub max_data_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum CharacterError {
+    #[error("save slot {0} does not exist")]
+    UnknownSlot(u32),
+
+    #[error("character data of {0} bytes is too large")]
+    DataTooLarge(usize),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CharacterStats {
+    pub vigor: u32,
+    pub attunement: u32,
+    pub endurance: u32,
+    pub vitality: u32,
+    pub strength: u32,
+    pub dexterity: u32,
+    pub intelligence: u32,
+    pub faith: u32,
+    pub luck: u32,
+}
+
+/// What we understand of a character's data.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Character {
+    pub name: Option<String>,
+    pub soul_level: Option<u32>,
+    pub stats: Option<CharacterStats>,
+    /// Item IDs of everything the character has equipped.
+    pub equipment: Vec<u32>,
+}
+
+/// The character data a player last uploaded for one of their save slots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CharacterSnapshot {
+    /// The decoded character, or None if the data couldn't be decoded.
+    pub character: Option<Character>,
+    /// The data as the client sent it, including anything we don't decode.
+    pub data: Vec<u8>,
+    /// When the data was uploaded, in milliseconds since the epoch.
+    pub uploaded_at: i64,
+}
+
+/// The part of a snapshot kept next to the raw data on disk.
+#[derive(Serialize, Deserialize)]
+struct CharacterRecord {
+    character: Option<Character>,
+    uploaded_at: i64,
+}
+
+/// Uploads by player and save slot.
+type Uploads = HashMap<(SteamId, u32), Arc<CharacterSnapshot>>;
+
+/// The character data each player last uploaded for each of their save slots, kept on disk.
+///
+/// Each slot is stored under the store's directory as the data the client sent, alongside what
+/// was decoded from it. Uploads are written by a background task so they never wait on the disk.
+#[derive(Clone, Debug)]
+pub struct CharacterStore {
+    limits: CharacterLimits,
+    dir: PathBuf,
+    /// Uploads that haven't been written yet. Only the latest for each slot is kept.
+    pending: Arc<Mutex<Uploads>>,
+}
+
+impl CharacterStore {
+    /// Keep characters in `dir`, which is created when the first one is written.
+    pub fn new(limits: CharacterLimits, dir: PathBuf) -> Self {
+        Self {
+            limits,
+            dir,
+            pending: Default::default(),
+        }
+    }
+
+    fn path(&self, steam_id: SteamId, character_id: u32, extension: &str) -> PathBuf {
+        self.dir
+            .join(steam_id.to_string())
+            .join(format!("{}.{}", character_id, extension))
+    }
+
+    /// Replace the player's character in `character_id` with a new upload. It is written by the
+    /// background task started with [CharacterStore::spawn_writer].
+    pub fn update(
+        &self,
+        steam_id: SteamId,
+        character_id: u32,
+        character: Option<Character>,
+        data: Vec<u8>,
+    ) -> Result<(), CharacterError> {
+        if character_id >= self.limits.slot_count {
+            return Err(CharacterError::UnknownSlot(character_id));
+        }
+
+        if data.len() > self.limits.max_data_size {
+            return Err(CharacterError::DataTooLarge(data.len()));
+        }
+
+        let snapshot = CharacterSnapshot {
+            character,
+            data,
+            uploaded_at: Utc::now().timestamp_millis(),
+        };
+        self.pending
+            .lock()
+            .insert((steam_id, character_id), Arc::new(snapshot));
+
+        Ok(())
+    }
+
+    /// The character the player last uploaded to `character_id`, if they have uploaded one. This
+    /// reads from disk unless the upload hasn't been written yet, so call it from the blocking
+    /// pool.
+    pub fn get(
+        &self,
+        steam_id: SteamId,
+        character_id: u32,
+    ) -> Result<Option<CharacterSnapshot>, StorageError> {
+        if let Some(snapshot) = self.pending.lock().get(&(steam_id, character_id)) {
+            return Ok(Some(CharacterSnapshot::clone(snapshot)));
+        }
+
+        let record: CharacterRecord =
+            match storage::load(&self.path(steam_id, character_id, "json"))? {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+
+        let data = match fs::read(self.path(steam_id, character_id, "bin")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(CharacterSnapshot {
+            character: record.character,
+            data,
+            uploaded_at: record.uploaded_at,
+        }))
+    }
+
+    /// Write uploads in the background every few seconds, one at a time on the blocking pool.
+    pub fn spawn_writer(&self) {
+        let store = self.clone();
+
+        tokio::spawn(async move {
+            let interval = Duration::from_secs(storage::WRITE_INTERVAL_SECS);
+            let mut interval = tokio::time::interval(interval);
+
+            loop {
+                interval.tick().await;
+
+                if store.pending.lock().is_empty() {
+                    continue;
+                }
+
+                let writer = store.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || writer.write_pending()).await {
+                    error!(error = %e, "Character writer failed");
+                }
+            }
+        });
+    }
+
+    /// Write every pending upload. An upload that fails to be written, or that was replaced while
+    /// it was being written, stays pending for the next time.
+    fn write_pending(&self) {
+        let uploads: Vec<_> = self
+            .pending
+            .lock()
+            .iter()
+            .map(|(key, snapshot)| (*key, snapshot.clone()))
+            .collect();
+
+        for ((steam_id, character_id), snapshot) in uploads {
+            if let Err(e) = self.write(steam_id, character_id, &snapshot) {
+                error!(
+                    steam_id = %steam_id,
+                    character_id,
+                    error = %e,
+                    "Unable to store character data, will try again"
+                );
+                continue;
+            }
+
+            let mut pending = self.pending.lock();
+            let key = (steam_id, character_id);
+
+            if pending
+                .get(&key)
+                .map_or(false, |latest| Arc::ptr_eq(latest, &snapshot))
+            {
+                pending.remove(&key);
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        steam_id: SteamId,
+        character_id: u32,
+        snapshot: &CharacterSnapshot,
+    ) -> Result<(), StorageError> {
+        let record = CharacterRecord {
+            character: snapshot.character.clone(),
+            uploaded_at: snapshot.uploaded_at,
+        };
+
+        storage::save_bytes(&self.path(steam_id, character_id, "bin"), &snapshot.data)?;
+        storage::save(&self.path(steam_id, character_id, "json"), &record)
+    }
+}
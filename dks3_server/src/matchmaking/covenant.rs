@@ -0,0 +1,386 @@
+//! Covenants that summon their members into other players' worlds automatically.
+//!
+//! Blue Sentinels and Blades of the Darkmoon are sent to defend Way of Blue hosts who have been
+//! invaded. Watchdogs of Farron and Aldrich Faithful are sent to hunt anyone who wanders into the
+//! areas they guard. A member who has just been summoned sits out for their covenant's cooldown,
+//! and only joins the world once they accept the summon.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::matchmaking::boss_defender::BossDefenderStore;
+use crate::matchmaking::invasion;
+use crate::matchmaking::rules::{MatchingItem, MatchingRules};
+use crate::matchmaking::session::{Session, SessionRegistry, SteamId};
+use crate::matchmaking::sign::SignStore;
+use crate::matchmaking::world::WorldStore;
+use crate::{read_int, ConfigError};
+
+const DEFAULT_COOLDOWN_SECS: i64 = 120;
+
+/// How long a member has to accept a summon before it lapses.
+const SUMMON_TIMEOUT_SECS: i64 = 30;
+
+/// The covenants, numbered as the client numbers them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Covenant {
+    WarriorOfSunlight,
+    WayOfBlue,
+    BlueSentinels,
+    BladeOfTheDarkmoon,
+    RosariasFingers,
+    WatchdogsOfFarron,
+    AldrichFaithful,
+    MoundMakers,
+    SpearsOfTheChurch,
+}
+
+impl TryFrom<u32> for Covenant {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let covenant = match value {
+            1 => Covenant::WarriorOfSunlight,
+            2 => Covenant::WayOfBlue,
+            3 => Covenant::BlueSentinels,
+            4 => Covenant::BladeOfTheDarkmoon,
+            5 => Covenant::RosariasFingers,
+            6 => Covenant::WatchdogsOfFarron,
+            7 => Covenant::AldrichFaithful,
+            8 => Covenant::MoundMakers,
+            9 => Covenant::SpearsOfTheChurch,
+            _ => return Err(value),
+        };
+
+        Ok(covenant)
+    }
+}
+
+impl From<Covenant> for u32 {
+    fn from(covenant: Covenant) -> Self {
+        match covenant {
+            Covenant::WarriorOfSunlight => 1,
+            Covenant::WayOfBlue => 2,
+            Covenant::BlueSentinels => 3,
+            Covenant::BladeOfTheDarkmoon => 4,
+            Covenant::RosariasFingers => 5,
+            Covenant::WatchdogsOfFarron => 6,
+            Covenant::AldrichFaithful => 7,
+            Covenant::MoundMakers => 8,
+            Covenant::SpearsOfTheChurch => 9,
+        }
+    }
+}
+
+impl Covenant {
+    /// The covenants whose members are summoned to defend invaded Way of Blue hosts.
+    pub const DEFENDERS: [Covenant; 2] = [Covenant::BlueSentinels, Covenant::BladeOfTheDarkmoon];
+
+    /// The covenants whose members are summoned to hunt intruders in the areas they guard.
+    pub const GUARDIANS: [Covenant; 2] = [Covenant::WatchdogsOfFarron, Covenant::AldrichFaithful];
+
+    /// The name of the config table this covenant's settings can be set in.
+    pub fn config_key(&self) -> &'static str {
+        match self {
+            Covenant::WarriorOfSunlight => "warrior_of_sunlight",
+            Covenant::WayOfBlue => "way_of_blue",
+            Covenant::BlueSentinels => "blue_sentinels",
+            Covenant::BladeOfTheDarkmoon => "blade_of_the_darkmoon",
+            Covenant::RosariasFingers => "rosarias_fingers",
+            Covenant::WatchdogsOfFarron => "watchdogs_of_farron",
+            Covenant::AldrichFaithful => "aldrich_faithful",
+            Covenant::MoundMakers => "mound_makers",
+            Covenant::SpearsOfTheChurch => "spears_of_the_church",
+        }
+    }
+}
+
+/// How long each covenant's members sit out after a summon, and the areas the guardian covenants
+/// protect.
+#[derive(Clone, Debug, Default)]
+pub struct CovenantSettings {
+    cooldowns: HashMap<Covenant, Duration>,
+    guarded_areas: HashMap<Covenant, Vec<u32>>,
+}
+
+impl CovenantSettings {
+    /// Read each covenant's `cooldown_secs` and, for the guardian covenants, `guarded_areas` from
+    /// the config file's `covenant` section.
+    pub fn load(config_file: &config::Config) -> Result<Self, ConfigError> {
+        let mut settings = Self::default();
+
+        for covenant in Covenant::DEFENDERS.iter().chain(&Covenant::GUARDIANS) {
+            let key = format!("covenant.{}.cooldown_secs", covenant.config_key());
+
+            let cooldown_secs = match read_int(config_file, &key)? {
+                Some(secs) if secs >= 0 => secs,
+                Some(_) => {
+                    return Err(ConfigError::invalid(&key, "a whole number, not below zero"))
+                }
+                None => DEFAULT_COOLDOWN_SECS,
+            };
+            settings
+                .cooldowns
+                .insert(*covenant, Duration::seconds(cooldown_secs));
+        }
+
+        for covenant in Covenant::GUARDIANS.iter() {
+            let key = format!("covenant.{}.guarded_areas", covenant.config_key());
+            let values = match config_file.get_array(&key) {
+                Ok(values) => values,
+                Err(config::ConfigError::NotFound(_)) => vec![],
+                Err(_) => return Err(ConfigError::invalid(&key, "a list of online area IDs")),
+            };
+
+            let areas = values
+                .into_iter()
+                .map(|area| {
+                    area.into_int()
+                        .ok()
+                        .and_then(|area| u32::try_from(area).ok())
+                        .ok_or_else(|| ConfigError::invalid(&key, "a list of online area IDs"))
+                })
+                .collect::<Result<_, _>>()?;
+
+            settings.guarded_areas.insert(*covenant, areas);
+        }
+
+        Ok(settings)
+    }
+
+    pub fn cooldown(&self, covenant: Covenant) -> Duration {
+        self.cooldowns
+            .get(&covenant)
+            .copied()
+            .unwrap_or_else(|| Duration::seconds(DEFAULT_COOLDOWN_SECS))
+    }
+
+    /// The guardian covenant protecting `online_area_id`, if any.
+    pub fn guardian_of(&self, online_area_id: u32) -> Option<Covenant> {
+        Covenant::GUARDIANS.iter().copied().find(|covenant| {
+            self.guarded_areas
+                .get(covenant)
+                .map_or(false, |areas| areas.contains(&online_area_id))
+        })
+    }
+}
+
+/// When each covenant member was last summoned.
+#[derive(Clone, Debug, Default)]
+pub struct CovenantCooldowns {
+    last_summoned: Arc<Mutex<HashMap<SteamId, DateTime<Utc>>>>,
+}
+
+impl CovenantCooldowns {
+    fn is_cooling_down(&self, member: SteamId, cooldown: Duration, now: DateTime<Utc>) -> bool {
+        self.last_summoned
+            .lock()
+            .get(&member)
+            .map_or(false, |last| now - *last < cooldown)
+    }
+
+    /// Start `member`'s cooldown, unless they are already in one. Returns whether it was started.
+    fn try_start(&self, member: SteamId, cooldown: Duration, now: DateTime<Utc>) -> bool {
+        let mut last_summoned = self.last_summoned.lock();
+
+        // Forget anyone whose cooldown is long over so this doesn't grow forever
+        last_summoned.retain(|_, last| now - *last < Duration::days(1));
+
+        match last_summoned.get(&member) {
+            Some(last) if now - *last < cooldown => false,
+            _ => {
+                last_summoned.insert(member, now);
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PendingSummon {
+    target: SteamId,
+    covenant: Covenant,
+    summoned_at: DateTime<Utc>,
+}
+
+impl PendingSummon {
+    fn has_lapsed(&self, now: DateTime<Utc>) -> bool {
+        now - self.summoned_at > Duration::seconds(SUMMON_TIMEOUT_SECS)
+    }
+}
+
+/// Summons sent to covenant members that they haven't answered yet, by member. A member has at
+/// most one, and it lapses if they don't answer in time, so nothing is held for them.
+#[derive(Clone, Debug, Default)]
+pub struct CovenantSummons {
+    pending: Arc<Mutex<HashMap<SteamId, PendingSummon>>>,
+}
+
+impl CovenantSummons {
+    /// Wait for `member` to answer a summon into `target`'s world, replacing any summon they
+    /// were sent before.
+    pub fn offer(&self, member: SteamId, target: SteamId, covenant: Covenant) {
+        let now = Utc::now();
+        let mut pending = self.pending.lock();
+
+        // Forget summons no one answered so this doesn't grow forever
+        pending.retain(|_, summon| !summon.has_lapsed(now));
+        pending.insert(
+            member,
+            PendingSummon {
+                target,
+                covenant,
+                summoned_at: now,
+            },
+        );
+    }
+
+    /// Take the summon `member` was sent into `target`'s world, for when they answer it. Returns
+    /// the covenant they were summoned for, or `None` if there's no such summon or it has
+    /// lapsed.
+    pub fn take(&self, member: SteamId, target: SteamId) -> Option<Covenant> {
+        let mut pending = self.pending.lock();
+
+        match pending.get(&member) {
+            Some(summon) if summon.target == target => {
+                let summon = pending.remove(&member)?;
+
+                if summon.has_lapsed(Utc::now()) {
+                    None
+                } else {
+                    Some(summon.covenant)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `member` has a summon they haven't answered yet.
+    fn is_pending(&self, member: SteamId, now: DateTime<Utc>) -> bool {
+        self.pending
+            .lock()
+            .get(&member)
+            .map_or(false, |summon| !summon.has_lapsed(now))
+    }
+}
+
+/// Everything needed to pick a covenant member to summon.
+pub struct CovenantMatcher<'a> {
+    pub sessions: &'a SessionRegistry,
+    pub worlds: &'a WorldStore,
+    pub signs: &'a SignStore,
+    pub boss_defenders: &'a BossDefenderStore,
+    pub rules: &'a MatchingRules,
+    pub settings: &'a CovenantSettings,
+    pub cooldowns: &'a CovenantCooldowns,
+    pub summons: &'a CovenantSummons,
+    pub max_phantoms: u32,
+}
+
+impl<'a> CovenantMatcher<'a> {
+    /// Pick a member of one of `covenants` to be summoned into `target`'s world, at random from
+    /// those who are free, off cooldown and match `target` through `item`. Members who are
+    /// invading, or have a sign, boss defender or covenant summon waiting on them, aren't free.
+    /// Their cooldown starts straight away. No one is picked if `target`'s world is full.
+    fn pick<R: Rng>(
+        &self,
+        covenants: &[Covenant],
+        item: MatchingItem,
+        target: &Session,
+        rng: &mut R,
+    ) -> Option<(Session, Covenant)> {
+        if !self.worlds.has_room(target.steam_id, self.max_phantoms) {
+            return None;
+        }
+
+        let now = Utc::now();
+        let invaders = invasion::invaders(self.sessions);
+
+        // Only look at the sessions while the registry is locked. The other stores are checked
+        // once it's released, since some of them lock the registry while they are locked.
+        let mut members = self.sessions.find(|member| {
+            let in_covenant = member
+                .status
+                .covenant
+                .map_or(false, |covenant| covenants.contains(&covenant));
+
+            in_covenant
+                && member.steam_id != target.steam_id
+                && member.game.is_some()
+                && member.status.is_alone()
+                && member.invasion.is_none()
+                && !invaders.contains(&member.steam_id)
+                && self
+                    .rules
+                    .can_match(item, &target.status.matching, &member.status.matching)
+        });
+
+        members.retain(|member| {
+            let cooldown = member
+                .status
+                .covenant
+                .map_or_else(Duration::zero, |covenant| self.settings.cooldown(covenant));
+
+            !self
+                .cooldowns
+                .is_cooling_down(member.steam_id, cooldown, now)
+                && !self.worlds.is_in_world(member.steam_id)
+                && !self.signs.is_being_summoned(member.steam_id)
+                && !self.boss_defenders.is_being_summoned(member.steam_id)
+                && !self.summons.is_pending(member.steam_id, now)
+        });
+
+        members.shuffle(rng);
+
+        members.into_iter().find_map(|member| {
+            let covenant = member.status.covenant?;
+            let cooldown = self.settings.cooldown(covenant);
+
+            if self.cooldowns.try_start(member.steam_id, cooldown, now) {
+                Some((member, covenant))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Pick a Blue Sentinel or Blade of the Darkmoon to defend `host`, if they are Way of Blue.
+    pub fn find_defender<R: Rng>(
+        &self,
+        host: &Session,
+        rng: &mut R,
+    ) -> Option<(Session, Covenant)> {
+        if host.status.covenant != Some(Covenant::WayOfBlue) {
+            return None;
+        }
+
+        self.pick(
+            &Covenant::DEFENDERS,
+            MatchingItem::CovenantDefender,
+            host,
+            rng,
+        )
+    }
+
+    /// Pick a guardian to hunt `intruder`, if they are in an area a guardian covenant protects.
+    /// Guardians don't hunt their own.
+    pub fn find_guardian<R: Rng>(
+        &self,
+        intruder: &Session,
+        rng: &mut R,
+    ) -> Option<(Session, Covenant)> {
+        let covenant = self.settings.guardian_of(intruder.status.online_area_id?)?;
+
+        if intruder.status.covenant == Some(covenant) {
+            return None;
+        }
+
+        self.pick(&[covenant], MatchingItem::AreaGuardian, intruder, rng)
+    }
+}
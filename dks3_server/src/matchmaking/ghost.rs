@@ -0,0 +1,178 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::matchmaking::blood_message::weighted_sample;
+use crate::matchmaking::session::SteamId;
+
+/// How quickly an older ghost becomes less likely to be served than a fresh one.
+const GHOST_HALF_LIFE_SECS: f64 = 600.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GhostLimits {
+    /// Ghosts kept for each online area. Once full, each new ghost replaces the area's oldest.
+    pub max_per_area: usize,
+    /// Total size of the recordings held across every area, in bytes.
+    pub memory_budget: usize,
+    /// Largest single recording a client may upload, in bytes.
+    pub max_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("ghost data of {0} bytes is too large")]
+pub struct GhostTooLarge(pub usize);
+
+/// A recording of a player moving through an area, replayed in other players' worlds.
+#[derive(Clone, Debug)]
+pub struct Ghost {
+    pub id: u64,
+    pub player: SteamId,
+    pub online_area_id: u32,
+    pub data: Bytes,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Default, Debug)]
+struct GhostState {
+    next_id: u64,
+    ghosts: HashMap<u64, Ghost>,
+    /// A ring of ghost IDs for each online area, oldest first.
+    areas: HashMap<u32, VecDeque<u64>>,
+    /// Every ghost ID in the order it was uploaded. Ghosts pushed out of their area's ring are
+    /// left here and skipped over, so this is compacted once it gets too far ahead of `ghosts`.
+    order: VecDeque<u64>,
+    total_size: usize,
+}
+
+impl GhostState {
+    fn remove(&mut self, id: u64) {
+        let ghost = match self.ghosts.remove(&id) {
+            Some(ghost) => ghost,
+            None => return,
+        };
+
+        self.total_size -= ghost.data.len();
+
+        if let Some(area) = self.areas.get_mut(&ghost.online_area_id) {
+            area.retain(|&other| other != id);
+
+            if area.is_empty() {
+                self.areas.remove(&ghost.online_area_id);
+            }
+        }
+    }
+
+    /// Remove the oldest ghosts, regardless of area, until the recordings fit in `budget`.
+    fn evict_to_budget(&mut self, budget: usize) {
+        while self.total_size > budget {
+            match self.order.pop_front() {
+                Some(oldest) => self.remove(oldest),
+                None => break,
+            }
+        }
+
+        if self.order.len() > 2 * self.ghosts.len() {
+            let ghosts = &self.ghosts;
+            self.order.retain(|id| ghosts.contains_key(id));
+        }
+    }
+}
+
+/// Recent ghost recordings, kept in a bounded ring for each online area.
+#[derive(Clone, Debug)]
+pub struct GhostStore {
+    limits: GhostLimits,
+    state: Arc<RwLock<GhostState>>,
+}
+
+impl GhostStore {
+    pub fn new(limits: GhostLimits) -> Self {
+        Self {
+            limits,
+            state: Default::default(),
+        }
+    }
+
+    pub fn upload(
+        &self,
+        player: SteamId,
+        online_area_id: u32,
+        data: Bytes,
+    ) -> Result<u64, GhostTooLarge> {
+        if data.len() > self.limits.max_size {
+            return Err(GhostTooLarge(data.len()));
+        }
+
+        let mut state = self.state.write();
+
+        state.next_id += 1;
+        let id = state.next_id;
+
+        state.total_size += data.len();
+        state.ghosts.insert(
+            id,
+            Ghost {
+                id,
+                player,
+                online_area_id,
+                data,
+                created_at: Utc::now(),
+            },
+        );
+        state.order.push_back(id);
+
+        let area = state.areas.entry(online_area_id).or_default();
+        area.push_back(id);
+
+        let evicted = if area.len() > self.limits.max_per_area {
+            area.front().copied()
+        } else {
+            None
+        };
+
+        if let Some(evicted) = evicted {
+            state.remove(evicted);
+        }
+
+        state.evict_to_budget(self.limits.memory_budget);
+
+        Ok(id)
+    }
+
+    /// Pick up to `count` ghosts from `online_area_id` for `requester` to see, favouring recent
+    /// recordings. Only the latest ghost from each player is considered so that one player
+    /// running back and forth doesn't fill the requester's world.
+    pub fn list<R: Rng>(
+        &self,
+        requester: SteamId,
+        online_area_id: u32,
+        count: usize,
+        rng: &mut R,
+    ) -> Vec<Ghost> {
+        let now = Utc::now();
+        let state = self.state.read();
+        let mut seen = HashSet::new();
+
+        let candidates = state
+            .areas
+            .get(&online_area_id)
+            .into_iter()
+            .flat_map(|area| area.iter().rev())
+            .filter_map(|id| state.ghosts.get(id))
+            .filter(|ghost| ghost.player != requester && seen.insert(ghost.player))
+            .map(|ghost| {
+                let age_secs = (now - ghost.created_at).num_seconds().max(0) as f64;
+                (ghost, 0.5f64.powf(age_secs / GHOST_HALF_LIFE_SECS))
+            });
+
+        weighted_sample(candidates, count, rng)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+}
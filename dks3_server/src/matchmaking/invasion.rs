@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::matchmaking::player_status::MultiplayerState;
+use crate::matchmaking::rules::{MatchingItem, MatchingParameters, MatchingRules};
+use crate::matchmaking::session::{Session, SessionRegistry, SteamId};
+use crate::matchmaking::world::WorldStore;
+
+/// How long a host has to answer a break-in before they can be offered to other invaders again.
+const BREAK_IN_TIMEOUT_SECS: i64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InvasionType {
+    RedEyeOrb,
+    CrackedRedEyeOrb,
+}
+
+impl TryFrom<u32> for InvasionType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(InvasionType::RedEyeOrb),
+            1 => Ok(InvasionType::CrackedRedEyeOrb),
+            _ => Err(value),
+        }
+    }
+}
+
+impl InvasionType {
+    pub fn matching_item(&self) -> MatchingItem {
+        match self {
+            InvasionType::RedEyeOrb | InvasionType::CrackedRedEyeOrb => MatchingItem::RedEyeOrb,
+        }
+    }
+}
+
+/// An invader in a host's world, or on their way there.
+#[derive(Clone, Debug)]
+pub struct Invasion {
+    pub invader: SteamId,
+    pub started_at: DateTime<Utc>,
+    /// Whether the host has let the invader in. Until then the host can still turn them away.
+    pub accepted: bool,
+}
+
+impl Invasion {
+    /// Whether the host never answered the break-in, so it no longer counts.
+    fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        !self.accepted && now - self.started_at > Duration::seconds(BREAK_IN_TIMEOUT_SECS)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum BreakInError {
+    #[error("host is not online")]
+    NotOnline,
+
+    #[error("host can't be invaded right now")]
+    NotInvadable,
+
+    #[error("host isn't in the area searched or doesn't match the invader")]
+    NotMatched,
+
+    #[error("players can't invade themselves")]
+    OwnWorld,
+
+    #[error("invader is already in a multiplayer session")]
+    InvaderBusy,
+}
+
+/// What an invader is looking for.
+#[derive(Clone, Debug)]
+pub struct BreakInSearch {
+    pub invader: SteamId,
+    pub matching: MatchingParameters,
+    pub online_area_id: u32,
+    pub invasion_type: InvasionType,
+}
+
+/// Whether `host` can be invaded: they have to be in the game in their own world, embered, have
+/// room for another phantom and not already have an invader. Phantoms are counted both as the
+/// host reports them and as the server has placed them.
+pub fn is_invadable(
+    host: &Session,
+    worlds: &WorldStore,
+    max_phantoms: u32,
+    now: DateTime<Utc>,
+) -> bool {
+    host.game.is_some()
+        && host.status.embered
+        && host.status.multiplayer_state != MultiplayerState::Guest
+        && host.status.phantom_count < max_phantoms
+        && worlds.has_room(host.steam_id, max_phantoms)
+        && host
+            .invasion
+            .as_ref()
+            .map_or(true, |invasion| invasion.is_stale(now))
+}
+
+/// Whether `host` is someone the invader described by `search` was looking for: someone else, in
+/// the area searched, who matches them under `rules`.
+fn matches_search(host: &Session, rules: &MatchingRules, search: &BreakInSearch) -> bool {
+    host.steam_id != search.invader
+        && host.status.online_area_id == Some(search.online_area_id)
+        && rules.can_match(
+            search.invasion_type.matching_item(),
+            &search.matching,
+            &host.status.matching,
+        )
+}
+
+/// Up to `count` hosts, in random order, that the invader described by `search` could break into.
+pub fn find_targets<R: Rng>(
+    sessions: &SessionRegistry,
+    worlds: &WorldStore,
+    rules: &MatchingRules,
+    max_phantoms: u32,
+    search: &BreakInSearch,
+    count: usize,
+    rng: &mut R,
+) -> Vec<Session> {
+    let now = Utc::now();
+
+    let mut targets = sessions.find_in_area(search.online_area_id, |host| {
+        matches_search(host, rules, search) && is_invadable(host, worlds, max_phantoms, now)
+    });
+
+    targets.shuffle(rng);
+    targets.truncate(count);
+    targets
+}
+
+/// Claim `host` for the invader described by `search` while the host decides whether to let them
+/// in, so no one else is sent to the same world. The host has to be one [find_targets] could
+/// have offered the invader, since clients can name any host they like.
+pub fn begin(
+    sessions: &SessionRegistry,
+    worlds: &WorldStore,
+    rules: &MatchingRules,
+    max_phantoms: u32,
+    search: &BreakInSearch,
+    host: SteamId,
+) -> Result<(), BreakInError> {
+    let invader = search.invader;
+
+    if host == invader {
+        return Err(BreakInError::OwnWorld);
+    }
+
+    if worlds.is_in_world(invader) {
+        return Err(BreakInError::InvaderBusy);
+    }
+
+    let now = Utc::now();
+
+    sessions
+        .update(host, |session| {
+            if !is_invadable(session, worlds, max_phantoms, now) {
+                return Err(BreakInError::NotInvadable);
+            }
+
+            if !matches_search(session, rules, search) {
+                return Err(BreakInError::NotMatched);
+            }
+
+            session.invasion = Some(Invasion {
+                invader,
+                started_at: now,
+                accepted: false,
+            });
+
+            Ok(())
+        })
+        .unwrap_or(Err(BreakInError::NotOnline))
+}
+
+/// Every player invading someone's world or waiting to hear back from a host they are breaking
+/// in on.
+pub fn invaders(sessions: &SessionRegistry) -> HashSet<SteamId> {
+    let now = Utc::now();
+
+    sessions
+        .find(|host| host.invasion.is_some())
+        .into_iter()
+        .filter_map(|host| host.invasion)
+        .filter(|invasion| !invasion.is_stale(now))
+        .map(|invasion| invasion.invader)
+        .collect()
+}
+
+/// The host let `invader` in. Returns false if they weren't expecting them.
+pub fn accept(sessions: &SessionRegistry, host: SteamId, invader: SteamId) -> bool {
+    sessions
+        .update(host, |session| match session.invasion.as_mut() {
+            Some(invasion) if invasion.invader == invader => {
+                invasion.accepted = true;
+                true
+            }
+            _ => false,
+        })
+        .unwrap_or(false)
+}
+
+/// The invasion of `host`'s world by `invader` failed or is over, so the host can be invaded
+/// again. Returns false if `invader` wasn't invading them.
+pub fn end(sessions: &SessionRegistry, host: SteamId, invader: SteamId) -> bool {
+    sessions
+        .update(host, |session| match &session.invasion {
+            Some(invasion) if invasion.invader == invader => {
+                session.invasion = None;
+                true
+            }
+            _ => false,
+        })
+        .unwrap_or(false)
+}
+
+/// End any invasion `invader` is part of, for when they leave. Returns the host they were
+/// invading.
+pub fn end_all_by(sessions: &SessionRegistry, invader: SteamId) -> Option<SteamId> {
+    let host = sessions
+        .find(|host| {
+            host.invasion
+                .as_ref()
+                .map_or(false, |invasion| invasion.invader == invader)
+        })
+        .into_iter()
+        .next()?;
+
+    if end(sessions, host.steam_id, invader) {
+        Some(host.steam_id)
+    } else {
+        None
+    }
+}
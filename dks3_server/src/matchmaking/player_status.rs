@@ -0,0 +1,61 @@
+use std::convert::TryFrom;
+
+use crate::matchmaking::covenant::Covenant;
+use crate::matchmaking::rules::MatchingParameters;
+
+/// Whether the player is in a multiplayer session.
+///
+/// The numbering (0 solo, 1 host, 2 guest) is our assumption and hasn't been checked against the
+/// client. Cleaning up after a player who reports being back on their own relies on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MultiplayerState {
+    /// On their own in their world.
+    Solo,
+    /// In their own world with phantoms.
+    Host,
+    /// A phantom in someone else's world.
+    Guest,
+}
+
+impl Default for MultiplayerState {
+    fn default() -> Self {
+        MultiplayerState::Solo
+    }
+}
+
+impl TryFrom<u32> for MultiplayerState {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let state = match value {
+            0 => MultiplayerState::Solo,
+            1 => MultiplayerState::Host,
+            2 => MultiplayerState::Guest,
+            _ => return Err(value),
+        };
+
+        Ok(state)
+    }
+}
+
+/// What the player has told us about themselves through their status updates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlayerStatus {
+    /// Where the player is, or None until they first say.
+    pub online_area_id: Option<u32>,
+    /// The player's levels and multiplayer password.
+    pub matching: MatchingParameters,
+    /// The covenant the player has equipped, if any.
+    pub covenant: Option<Covenant>,
+    pub embered: bool,
+    pub multiplayer_state: MultiplayerState,
+    /// The phantoms in the player's world other than themselves.
+    pub phantom_count: u32,
+}
+
+impl PlayerStatus {
+    /// Whether the player is on their own, neither hosting phantoms nor in someone else's world.
+    pub fn is_alone(&self) -> bool {
+        self.multiplayer_state == MultiplayerState::Solo && self.phantom_count == 0
+    }
+}
@@ -0,0 +1,763 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use thiserror::Error;
+
+use crate::matchmaking::rules::{MatchingItem, MatchingParameters, MatchingRules};
+use crate::matchmaking::session::SteamId;
+
+/// The map ID players send when they are happy to fight on any map.
+pub const ANY_MAP: u32 = 0;
+
+/// Finished matches kept once their result is in.
+const MAX_FINISHED_MATCHES: usize = 1024;
+
+/// How long a match can go without a result before it is assumed abandoned.
+const MATCH_TIMEOUT_MINS: i64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QuickMatchMode {
+    Duel,
+    TwoVsTwo,
+    ThreeVsThree,
+    FreeForAll,
+}
+
+impl TryFrom<u32> for QuickMatchMode {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(QuickMatchMode::Duel),
+            1 => Ok(QuickMatchMode::TwoVsTwo),
+            2 => Ok(QuickMatchMode::ThreeVsThree),
+            3 => Ok(QuickMatchMode::FreeForAll),
+            _ => Err(value),
+        }
+    }
+}
+
+impl From<QuickMatchMode> for u32 {
+    fn from(mode: QuickMatchMode) -> Self {
+        match mode {
+            QuickMatchMode::Duel => 0,
+            QuickMatchMode::TwoVsTwo => 1,
+            QuickMatchMode::ThreeVsThree => 2,
+            QuickMatchMode::FreeForAll => 3,
+        }
+    }
+}
+
+impl QuickMatchMode {
+    /// How many players a match in this mode needs.
+    pub fn player_count(&self) -> usize {
+        match self {
+            QuickMatchMode::Duel => 2,
+            QuickMatchMode::TwoVsTwo => 4,
+            QuickMatchMode::ThreeVsThree => 6,
+            QuickMatchMode::FreeForAll => 4,
+        }
+    }
+
+    /// The team of the player ranked `rank` by rating in a match, best first. Teams are numbered
+    /// from 1 and picked in a snake order (1, 2, 2, 1, 1, 2) so neither team gets all the best
+    /// players. In a free for all everyone is on their own.
+    fn team(&self, rank: usize) -> u32 {
+        match self {
+            QuickMatchMode::FreeForAll => rank as u32 + 1,
+            _ if rank % 4 < 2 => rank as u32 % 2 + 1,
+            _ => 2 - rank as u32 % 2,
+        }
+    }
+}
+
+/// How far apart in rating players can be and still be put in a match together. The window
+/// starts narrow and widens the longer a player waits, so no one waits forever.
+#[derive(Clone, Copy, Debug)]
+pub struct RatingWindow {
+    pub initial: f64,
+    pub growth_per_min: f64,
+    pub max: f64,
+}
+
+impl RatingWindow {
+    fn width(&self, waited: Duration) -> f64 {
+        let waited_mins = waited.num_milliseconds().max(0) as f64 / 60_000.0;
+
+        (self.initial + self.growth_per_min * waited_mins).min(self.max)
+    }
+}
+
+/// What a player is looking for in a match.
+#[derive(Clone, Debug)]
+pub struct QuickMatchSearch {
+    pub steam_id: SteamId,
+    pub mode: QuickMatchMode,
+    /// The map the player picked, or [ANY_MAP].
+    pub map_id: u32,
+    pub matching: MatchingParameters,
+    /// The player's rating in the mode they are searching for.
+    pub rating: f64,
+}
+
+/// A player waiting to be put into a match.
+#[derive(Clone, Debug)]
+struct QuickMatchEntry {
+    search: QuickMatchSearch,
+    queued_at: DateTime<Utc>,
+}
+
+impl QuickMatchEntry {
+    fn accepts_map(&self, map_id: u32) -> bool {
+        let own_map_id = self.search.map_id;
+
+        own_map_id == ANY_MAP || map_id == ANY_MAP || own_map_id == map_id
+    }
+
+    /// Whether the two players are close enough in rating, given how long each has waited.
+    fn within_rating_window(
+        &self,
+        other: &QuickMatchEntry,
+        window: &RatingWindow,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let width = window
+            .width(now - self.queued_at)
+            .max(window.width(now - other.queued_at));
+
+        (self.search.rating - other.search.rating).abs() <= width
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuickMatchPlayer {
+    pub steam_id: SteamId,
+    pub team: u32,
+}
+
+/// A player's account of how a match went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuickMatchReport {
+    pub steam_id: SteamId,
+    /// The team that won, or None for a draw.
+    pub winning_team: Option<u32>,
+    pub reported_at: DateTime<Utc>,
+}
+
+/// A match's outcome, once its players' reports agree on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuickMatchOutcome {
+    /// The team that won, or None for a draw.
+    pub winning_team: Option<u32>,
+    pub decided_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct QuickMatch {
+    pub id: u64,
+    pub mode: QuickMatchMode,
+    /// The map to fight on, or [ANY_MAP] if no one had a preference and the host can choose.
+    pub map_id: u32,
+    /// The player whose world the match is held in. This is whoever had been waiting longest.
+    pub host: SteamId,
+    pub players: Vec<QuickMatchPlayer>,
+    pub formed_at: DateTime<Utc>,
+    /// The results players have reported so far, in the order they came in.
+    pub reports: Vec<QuickMatchReport>,
+    pub outcome: Option<QuickMatchOutcome>,
+}
+
+impl QuickMatch {
+    pub fn team_of(&self, steam_id: SteamId) -> Option<u32> {
+        self.players
+            .iter()
+            .find(|player| player.steam_id == steam_id)
+            .map(|player| player.team)
+    }
+
+    /// Whether enough players have reported for the result to be decided: someone from every
+    /// team, or more than half of the players. No single player can decide a match on their own.
+    fn has_quorum(&self) -> bool {
+        let every_team_reported = self.players.iter().all(|player| {
+            self.reports
+                .iter()
+                .any(|report| self.team_of(report.steam_id) == Some(player.team))
+        });
+
+        every_team_reported || self.reports.len() * 2 > self.players.len()
+    }
+
+    /// The winning team every report agrees on, with None for a draw. Returns None if the reports
+    /// disagree.
+    fn agreed_winner(&self) -> Option<Option<u32>> {
+        let winning_team = self.reports.first()?.winning_team;
+
+        if self
+            .reports
+            .iter()
+            .all(|report| report.winning_team == winning_team)
+        {
+            Some(winning_team)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where a match stands after one of its players has reported the result.
+#[derive(Clone, Debug)]
+pub enum QuickMatchProgress {
+    /// Not enough players have reported yet.
+    Waiting,
+    /// Enough players reported the same result, so the match is over with that outcome.
+    Finished(QuickMatch),
+    /// Players reported different results, so the match is over without one.
+    Disputed(QuickMatch),
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum QuickMatchRegisterError {
+    #[error("player is still in a match that hasn't finished")]
+    InMatch,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum QuickMatchResultError {
+    #[error("match does not exist or has already finished")]
+    NotFound,
+
+    #[error("player was not in the match")]
+    NotInMatch,
+
+    #[error("player has already reported the match's result")]
+    AlreadyReported,
+
+    #[error("winning team {0} is not in the match")]
+    UnknownTeam(u32),
+}
+
+#[derive(Default, Debug)]
+struct QuickMatchState {
+    next_id: u64,
+    /// Players waiting for a match, longest waiting first.
+    queue: Vec<QuickMatchEntry>,
+    /// Matches that have been formed and are waiting for a result.
+    matches: HashMap<u64, QuickMatch>,
+    /// The match each player is in.
+    players: HashMap<SteamId, u64>,
+    /// The most recently finished matches, oldest first.
+    finished: VecDeque<QuickMatch>,
+}
+
+impl QuickMatchState {
+    /// Pick players for one match from the queue, if enough compatible players are waiting. The
+    /// longest waiting player is tried as the anchor first, and the players closest to them in
+    /// rating are added as long as they match everyone already picked.
+    fn pick_group(
+        &self,
+        rules: &MatchingRules,
+        window: &RatingWindow,
+        now: DateTime<Utc>,
+    ) -> Option<Vec<usize>> {
+        let compatible = |a: &QuickMatchEntry, b: &QuickMatchEntry| {
+            let (a_matching, b_matching) = (&a.search.matching, &b.search.matching);
+
+            rules.can_match(MatchingItem::QuickMatch, a_matching, b_matching)
+                && rules.can_match(MatchingItem::QuickMatch, b_matching, a_matching)
+                && a.within_rating_window(b, window, now)
+        };
+
+        for (anchor_index, anchor) in self.queue.iter().enumerate() {
+            let mode = anchor.search.mode;
+            let mut group = vec![anchor_index];
+            let mut map_id = anchor.search.map_id;
+
+            let mut candidates: Vec<_> = (anchor_index + 1..self.queue.len())
+                .filter(|&index| self.queue[index].search.mode == mode)
+                .collect();
+            candidates.sort_by(|&a, &b| {
+                let distance =
+                    |index: usize| (self.queue[index].search.rating - anchor.search.rating).abs();
+
+                distance(a)
+                    .partial_cmp(&distance(b))
+                    .unwrap_or(Ordering::Equal)
+                    .then(a.cmp(&b))
+            });
+
+            for index in candidates {
+                let entry = &self.queue[index];
+
+                if !entry.accepts_map(map_id)
+                    || !group.iter().all(|&i| compatible(&self.queue[i], entry))
+                {
+                    continue;
+                }
+
+                group.push(index);
+                if map_id == ANY_MAP {
+                    map_id = entry.search.map_id;
+                }
+
+                if group.len() == mode.player_count() {
+                    group.sort_unstable();
+                    return Some(group);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn form_match(&mut self, group: Vec<usize>, now: DateTime<Utc>) -> QuickMatch {
+        let entries: Vec<_> = group
+            .iter()
+            .map(|&i| self.queue[i].search.clone())
+            .collect();
+
+        for &index in group.iter().rev() {
+            self.queue.remove(index);
+        }
+
+        let mut by_rating: Vec<_> = (0..entries.len()).collect();
+        by_rating.sort_by(|&a, &b| {
+            entries[b]
+                .rating
+                .partial_cmp(&entries[a].rating)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let mode = entries[0].mode;
+        let mut teams = vec![0; entries.len()];
+        for (rank, &index) in by_rating.iter().enumerate() {
+            teams[index] = mode.team(rank);
+        }
+
+        self.next_id += 1;
+        let quick_match = QuickMatch {
+            id: self.next_id,
+            mode,
+            map_id: entries
+                .iter()
+                .map(|entry| entry.map_id)
+                .find(|&map_id| map_id != ANY_MAP)
+                .unwrap_or(ANY_MAP),
+            host: entries[0].steam_id,
+            players: entries
+                .iter()
+                .zip(teams)
+                .map(|(entry, team)| QuickMatchPlayer {
+                    steam_id: entry.steam_id,
+                    team,
+                })
+                .collect(),
+            formed_at: now,
+            reports: vec![],
+            outcome: None,
+        };
+
+        for player in &quick_match.players {
+            self.players.insert(player.steam_id, quick_match.id);
+        }
+        self.matches.insert(quick_match.id, quick_match.clone());
+
+        quick_match
+    }
+
+    fn finish(&mut self, id: u64) -> Option<QuickMatch> {
+        let quick_match = self.matches.remove(&id)?;
+
+        for player in &quick_match.players {
+            if self.players.get(&player.steam_id) == Some(&id) {
+                self.players.remove(&player.steam_id);
+            }
+        }
+
+        if quick_match.outcome.is_some() {
+            if self.finished.len() == MAX_FINISHED_MATCHES {
+                self.finished.pop_front();
+            }
+            self.finished.push_back(quick_match.clone());
+        }
+
+        Some(quick_match)
+    }
+
+    /// Drop matches that never got a result.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let expired: Vec<_> = self
+            .matches
+            .values()
+            .filter(|quick_match| {
+                now - quick_match.formed_at > Duration::minutes(MATCH_TIMEOUT_MINS)
+            })
+            .map(|quick_match| quick_match.id)
+            .collect();
+
+        for id in expired {
+            self.finish(id);
+        }
+    }
+}
+
+/// Arena players waiting for a match, the matches they have been put in and the results of the
+/// ones that have finished.
+#[derive(Clone, Debug, Default)]
+pub struct QuickMatchStore {
+    state: Arc<RwLock<QuickMatchState>>,
+}
+
+impl QuickMatchStore {
+    /// Put a player in the queue, replacing any search they already had going, then form as many
+    /// matches as the queue allows. Returns the new matches. Players can't queue again until the
+    /// match they are in has finished.
+    pub fn register(
+        &self,
+        rules: &MatchingRules,
+        window: &RatingWindow,
+        search: QuickMatchSearch,
+    ) -> Result<Vec<QuickMatch>, QuickMatchRegisterError> {
+        {
+            let now = Utc::now();
+            let mut state = self.state.write();
+
+            state.expire(now);
+
+            if state.players.contains_key(&search.steam_id) {
+                return Err(QuickMatchRegisterError::InMatch);
+            }
+
+            state
+                .queue
+                .retain(|entry| entry.search.steam_id != search.steam_id);
+            state.queue.push(QuickMatchEntry {
+                search,
+                queued_at: now,
+            });
+        }
+
+        Ok(self.form_matches(rules, window))
+    }
+
+    /// Form as many matches as the queue allows, for when rating windows have widened since the
+    /// last player joined. Returns the new matches.
+    pub fn form_matches(&self, rules: &MatchingRules, window: &RatingWindow) -> Vec<QuickMatch> {
+        let now = Utc::now();
+        let mut state = self.state.write();
+
+        state.expire(now);
+
+        let mut formed = vec![];
+        while let Some(group) = state.pick_group(rules, window, now) {
+            formed.push(state.form_match(group, now));
+        }
+
+        formed
+    }
+
+    /// Take a player out of the queue. Returns false if they weren't in it.
+    pub fn unregister(&self, steam_id: SteamId) -> bool {
+        let mut state = self.state.write();
+        let queued = state.queue.len();
+
+        state
+            .queue
+            .retain(|entry| entry.search.steam_id != steam_id);
+        state.queue.len() != queued
+    }
+
+    /// Every player waiting for a match.
+    pub fn queued(&self) -> Vec<SteamId> {
+        self.state
+            .read()
+            .queue
+            .iter()
+            .map(|entry| entry.search.steam_id)
+            .collect()
+    }
+
+    /// Whether the player is waiting for a match.
+    pub fn is_queued(&self, steam_id: SteamId) -> bool {
+        self.state
+            .read()
+            .queue
+            .iter()
+            .any(|entry| entry.search.steam_id == steam_id)
+    }
+
+    /// The match the player has been put in and that hasn't finished yet, if any.
+    pub fn current_match(&self, steam_id: SteamId) -> Option<QuickMatch> {
+        let state = self.state.read();
+        let id = state.players.get(&steam_id)?;
+
+        state.matches.get(id).cloned()
+    }
+
+    /// Record one player's report of a match's result. The match finishes once someone from every
+    /// team, or most of its players, have reported. If their reports agree that is the outcome,
+    /// otherwise the match ends without one. Each player can only report once.
+    pub fn report(
+        &self,
+        id: u64,
+        reported_by: SteamId,
+        winning_team: Option<u32>,
+    ) -> Result<QuickMatchProgress, QuickMatchResultError> {
+        let now = Utc::now();
+        let mut state = self.state.write();
+        let quick_match = state
+            .matches
+            .get_mut(&id)
+            .ok_or(QuickMatchResultError::NotFound)?;
+
+        if quick_match.team_of(reported_by).is_none() {
+            return Err(QuickMatchResultError::NotInMatch);
+        }
+
+        if let Some(team) = winning_team {
+            if !quick_match.players.iter().any(|player| player.team == team) {
+                return Err(QuickMatchResultError::UnknownTeam(team));
+            }
+        }
+
+        if quick_match
+            .reports
+            .iter()
+            .any(|report| report.steam_id == reported_by)
+        {
+            return Err(QuickMatchResultError::AlreadyReported);
+        }
+
+        quick_match.reports.push(QuickMatchReport {
+            steam_id: reported_by,
+            winning_team,
+            reported_at: now,
+        });
+
+        if !quick_match.has_quorum() {
+            return Ok(QuickMatchProgress::Waiting);
+        }
+
+        let winner = quick_match.agreed_winner();
+        quick_match.outcome = winner.map(|winning_team| QuickMatchOutcome {
+            winning_team,
+            decided_at: now,
+        });
+
+        let quick_match = state.finish(id).expect("match was just found");
+
+        if winner.is_some() {
+            Ok(QuickMatchProgress::Finished(quick_match))
+        } else {
+            Ok(QuickMatchProgress::Disputed(quick_match))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: RatingWindow = RatingWindow {
+        initial: 1000.0,
+        growth_per_min: 0.0,
+        max: 1000.0,
+    };
+
+    fn search(steam_id: u64, mode: QuickMatchMode, rating: f64) -> QuickMatchSearch {
+        QuickMatchSearch {
+            steam_id: SteamId::new(steam_id),
+            mode,
+            map_id: ANY_MAP,
+            matching: MatchingParameters {
+                soul_level: 100,
+                weapon_level: 5,
+                password: None,
+            },
+            rating,
+        }
+    }
+
+    /// Queue a player with each of `ratings`, in order, with steamids counting up from 1. Returns
+    /// the match they form.
+    fn formed(mode: QuickMatchMode, ratings: &[f64]) -> (QuickMatchStore, QuickMatch) {
+        let store = QuickMatchStore::default();
+        let rules = MatchingRules::default();
+        let mut formed = vec![];
+
+        for (index, &rating) in ratings.iter().enumerate() {
+            formed = store
+                .register(&rules, &WINDOW, search(index as u64 + 1, mode, rating))
+                .unwrap();
+        }
+
+        assert_eq!(formed.len(), 1);
+        (store, formed.remove(0))
+    }
+
+    fn teams(quick_match: &QuickMatch) -> Vec<u32> {
+        (1..=quick_match.players.len() as u64)
+            .map(|steam_id| quick_match.team_of(SteamId::new(steam_id)).unwrap())
+            .collect()
+    }
+
+    fn report(
+        store: &QuickMatchStore,
+        quick_match: &QuickMatch,
+        steam_id: u64,
+        winning_team: Option<u32>,
+    ) -> QuickMatchProgress {
+        store
+            .report(quick_match.id, SteamId::new(steam_id), winning_team)
+            .unwrap()
+    }
+
+    #[test]
+    fn teams_are_picked_in_snake_order() {
+        let ranks = |mode: QuickMatchMode| -> Vec<u32> {
+            (0..mode.player_count())
+                .map(|rank| mode.team(rank))
+                .collect()
+        };
+
+        assert_eq!(ranks(QuickMatchMode::Duel), vec![1, 2]);
+        assert_eq!(ranks(QuickMatchMode::TwoVsTwo), vec![1, 2, 2, 1]);
+        assert_eq!(ranks(QuickMatchMode::ThreeVsThree), vec![1, 2, 2, 1, 1, 2]);
+        assert_eq!(ranks(QuickMatchMode::FreeForAll), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn formed_match_splits_players_by_rating() {
+        let (store, quick_match) =
+            formed(QuickMatchMode::TwoVsTwo, &[1500.0, 1600.0, 1400.0, 1550.0]);
+
+        // Best to worst the players are 2, 4, 1, 3.
+        assert_eq!(teams(&quick_match), vec![2, 1, 1, 2]);
+        assert_eq!(quick_match.host, SteamId::new(1));
+        assert!(store.queued().is_empty());
+        assert_eq!(
+            store.current_match(SteamId::new(3)).unwrap().id,
+            quick_match.id
+        );
+    }
+
+    #[test]
+    fn free_for_all_puts_everyone_on_their_own_team() {
+        let (_, quick_match) = formed(
+            QuickMatchMode::FreeForAll,
+            &[1500.0, 1600.0, 1400.0, 1550.0],
+        );
+
+        assert_eq!(teams(&quick_match), vec![3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn one_report_cannot_decide_a_duel() {
+        let (store, quick_match) = formed(QuickMatchMode::Duel, &[1500.0, 1500.0]);
+
+        assert!(matches!(
+            report(&store, &quick_match, 1, Some(1)),
+            QuickMatchProgress::Waiting
+        ));
+
+        match report(&store, &quick_match, 2, Some(1)) {
+            QuickMatchProgress::Finished(finished) => {
+                assert_eq!(finished.outcome.unwrap().winning_team, Some(1));
+                assert_eq!(finished.reports.len(), 2);
+            }
+            progress => panic!("expected the duel to finish, got {:?}", progress),
+        }
+        assert!(store.current_match(SteamId::new(1)).is_none());
+    }
+
+    #[test]
+    fn one_team_alone_is_not_a_quorum() {
+        let (store, quick_match) =
+            formed(QuickMatchMode::TwoVsTwo, &[1600.0, 1500.0, 1500.0, 1400.0]);
+        let (team_1, team_2): (Vec<&QuickMatchPlayer>, Vec<_>) = quick_match
+            .players
+            .iter()
+            .partition(|player| player.team == 1);
+
+        // Half of the players, all from one team.
+        for player in &team_1 {
+            let progress = report(&store, &quick_match, player.steam_id.as_u64(), Some(1));
+            assert!(matches!(progress, QuickMatchProgress::Waiting));
+        }
+
+        let progress = report(&store, &quick_match, team_2[0].steam_id.as_u64(), Some(1));
+        assert!(matches!(progress, QuickMatchProgress::Finished(_)));
+    }
+
+    #[test]
+    fn free_for_all_needs_a_majority() {
+        let (store, quick_match) = formed(QuickMatchMode::FreeForAll, &[1500.0; 4]);
+
+        assert!(matches!(
+            report(&store, &quick_match, 1, Some(2)),
+            QuickMatchProgress::Waiting
+        ));
+        assert!(matches!(
+            report(&store, &quick_match, 2, Some(2)),
+            QuickMatchProgress::Waiting
+        ));
+        assert!(matches!(
+            report(&store, &quick_match, 3, Some(2)),
+            QuickMatchProgress::Finished(_)
+        ));
+    }
+
+    #[test]
+    fn disagreeing_reports_end_the_match_without_an_outcome() {
+        let (store, quick_match) = formed(QuickMatchMode::Duel, &[1500.0, 1500.0]);
+
+        report(&store, &quick_match, 1, Some(1));
+        match report(&store, &quick_match, 2, Some(2)) {
+            QuickMatchProgress::Disputed(disputed) => assert!(disputed.outcome.is_none()),
+            progress => panic!("expected the duel to be disputed, got {:?}", progress),
+        }
+    }
+
+    #[test]
+    fn draws_count_as_agreement() {
+        let (store, quick_match) = formed(QuickMatchMode::Duel, &[1500.0, 1500.0]);
+
+        report(&store, &quick_match, 1, None);
+        match report(&store, &quick_match, 2, None) {
+            QuickMatchProgress::Finished(finished) => {
+                assert_eq!(finished.outcome.unwrap().winning_team, None)
+            }
+            progress => panic!("expected the duel to finish, got {:?}", progress),
+        }
+    }
+
+    #[test]
+    fn invalid_reports_are_rejected() {
+        let (store, quick_match) = formed(QuickMatchMode::Duel, &[1500.0, 1500.0]);
+        let id = quick_match.id;
+
+        assert_eq!(
+            store.report(id, SteamId::new(3), Some(1)).unwrap_err(),
+            QuickMatchResultError::NotInMatch
+        );
+        assert_eq!(
+            store.report(id, SteamId::new(1), Some(3)).unwrap_err(),
+            QuickMatchResultError::UnknownTeam(3)
+        );
+
+        report(&store, &quick_match, 1, Some(1));
+        assert_eq!(
+            store.report(id, SteamId::new(1), Some(1)).unwrap_err(),
+            QuickMatchResultError::AlreadyReported
+        );
+
+        report(&store, &quick_match, 2, Some(1));
+        assert_eq!(
+            store.report(id, SteamId::new(2), Some(1)).unwrap_err(),
+            QuickMatchResultError::NotFound
+        );
+    }
+}
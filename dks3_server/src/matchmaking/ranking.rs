@@ -0,0 +1,355 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::Utc;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::matchmaking::session::SteamId;
+use crate::storage::{self, Dirty, StorageError};
+
+#[derive(Clone, Copy, Debug)]
+pub struct RankingLimits {
+    /// Boards are numbered from 0, so only IDs below this are accepted.
+    pub board_count: u32,
+    /// Largest data, in bytes, a client may show next to their score.
+    pub max_data_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum RankingError {
+    #[error("ranking board {0} does not exist")]
+    UnknownBoard(u32),
+
+    #[error("ranking data of {0} bytes is too large")]
+    DataTooLarge(usize),
+}
+
+/// A player's entry on a ranking board.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RankingEntry {
+    pub steam_id: u64,
+    pub score: u32,
+    /// What the client shows next to the score, such as the character's name.
+    pub data: Vec<u8>,
+    /// When the player first reached their current score, in milliseconds since the epoch.
+    pub scored_at: i64,
+}
+
+impl RankingEntry {
+    /// Where the entry sorts on its board. Higher scores come first, then whoever reached the
+    /// score earliest, then the lower steamid, so no two entries ever share a rank.
+    fn sort_key(&self) -> (Reverse<u32>, i64, u64) {
+        (Reverse(self.score), self.scored_at, self.steam_id)
+    }
+}
+
+/// One board's entries, kept sorted best first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Board {
+    entries: Vec<RankingEntry>,
+}
+
+impl Board {
+    fn position(&self, steam_id: SteamId) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.steam_id == steam_id.as_u64())
+    }
+
+    fn insert(&mut self, entry: RankingEntry) -> usize {
+        let key = entry.sort_key();
+        let index = match self
+            .entries
+            .binary_search_by(|other| other.sort_key().cmp(&key))
+        {
+            Ok(index) | Err(index) => index,
+        };
+
+        self.entries.insert(index, entry);
+        index
+    }
+}
+
+/// A ranked entry as returned by queries. Ranks are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RankedEntry {
+    pub rank: u32,
+    pub entry: RankingEntry,
+}
+
+/// The game's leaderboards, such as covenant medal counts, keyed by board ID and kept on disk.
+#[derive(Clone, Debug)]
+pub struct RankingStore {
+    limits: RankingLimits,
+    path: PathBuf,
+    boards: Arc<RwLock<HashMap<u32, Board>>>,
+    dirty: Dirty,
+}
+
+impl RankingStore {
+    /// Load the boards stored at `path`, starting afresh if there are none yet.
+    pub fn load(limits: RankingLimits, path: PathBuf) -> Result<Self, StorageError> {
+        let mut boards: HashMap<u32, Board> = storage::load(&path)?.unwrap_or_default();
+
+        // Keep the order right even if the file was edited by hand
+        for board in boards.values_mut() {
+            board.entries.sort_by_key(RankingEntry::sort_key);
+        }
+
+        Ok(Self {
+            limits,
+            path,
+            boards: Arc::new(RwLock::new(boards)),
+            dirty: Dirty::default(),
+        })
+    }
+
+    /// Start storing the boards in the background whenever they change.
+    pub fn spawn_writer(&self) {
+        let boards = self.boards.clone();
+
+        storage::spawn_writer(self.path.clone(), self.dirty.clone(), move || {
+            boards.read().clone()
+        });
+    }
+
+    /// Set the player's score on a board, replacing their previous one. Returns the player's new
+    /// rank.
+    pub fn register(
+        &self,
+        board_id: u32,
+        steam_id: SteamId,
+        score: u32,
+        data: Vec<u8>,
+    ) -> Result<u32, RankingError> {
+        if board_id >= self.limits.board_count {
+            return Err(RankingError::UnknownBoard(board_id));
+        }
+
+        if data.len() > self.limits.max_data_size {
+            return Err(RankingError::DataTooLarge(data.len()));
+        }
+
+        let mut boards = self.boards.write();
+        let board = boards.entry(board_id).or_default();
+
+        let previous = board
+            .position(steam_id)
+            .map(|index| board.entries.remove(index));
+
+        // A player who registers the same score again keeps their place among those tied with them
+        let scored_at = match previous {
+            Some(previous) if previous.score == score => previous.scored_at,
+            _ => Utc::now().timestamp_millis(),
+        };
+
+        let index = board.insert(RankingEntry {
+            steam_id: steam_id.as_u64(),
+            score,
+            data,
+            scored_at,
+        });
+
+        self.dirty.mark();
+
+        Ok(index as u32 + 1)
+    }
+
+    /// Up to `count` entries from a board, with the page centred on `rank` where possible.
+    pub fn around_rank(&self, board_id: u32, rank: u32, count: usize) -> Vec<RankedEntry> {
+        let boards = self.boards.read();
+        let board = match boards.get(&board_id) {
+            Some(board) => board,
+            None => return vec![],
+        };
+
+        let centre = (rank.max(1) as usize - 1).min(board.entries.len().saturating_sub(1));
+        let start = centre
+            .saturating_sub(count / 2)
+            .min(board.entries.len().saturating_sub(count));
+
+        board
+            .entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count)
+            .map(|(index, entry)| RankedEntry {
+                rank: index as u32 + 1,
+                entry: entry.clone(),
+            })
+            .collect()
+    }
+
+    /// Up to `count` entries from a board, with the page centred on the player. Players who aren't
+    /// on the board get the top of it.
+    pub fn around_player(
+        &self,
+        board_id: u32,
+        steam_id: SteamId,
+        count: usize,
+    ) -> Vec<RankedEntry> {
+        let rank = self.rank(board_id, steam_id).unwrap_or(1);
+
+        self.around_rank(board_id, rank, count)
+    }
+
+    /// How many players are on a board.
+    pub fn count(&self, board_id: u32) -> usize {
+        self.boards
+            .read()
+            .get(&board_id)
+            .map_or(0, |board| board.entries.len())
+    }
+
+    /// The player's rank on a board, if they are on it.
+    pub fn rank(&self, board_id: u32, steam_id: SteamId) -> Option<u32> {
+        let boards = self.boards.read();
+        let index = boards.get(&board_id)?.position(steam_id)?;
+
+        Some(index as u32 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: RankingLimits = RankingLimits {
+        board_count: 4,
+        max_data_size: 16,
+    };
+
+    fn store() -> RankingStore {
+        // Nothing is written unless the writer is started, so the file never exists
+        let path = std::env::temp_dir().join("dks3-rankings-test.json");
+
+        RankingStore::load(LIMITS, path).unwrap()
+    }
+
+    fn entry(steam_id: u64, score: u32, scored_at: i64) -> RankingEntry {
+        RankingEntry {
+            steam_id,
+            score,
+            data: vec![],
+            scored_at,
+        }
+    }
+
+    /// A store whose board 0 holds `count` players with descending scores, so player `n` has
+    /// rank `n`.
+    fn board_of(count: u64) -> RankingStore {
+        let store = store();
+        let mut boards = store.boards.write();
+        let board = boards.entry(0).or_default();
+
+        for steam_id in 1..=count {
+            board.insert(entry(steam_id, 1000 - steam_id as u32, 0));
+        }
+
+        drop(boards);
+        store
+    }
+
+    fn ranks(entries: &[RankedEntry]) -> Vec<u32> {
+        entries.iter().map(|ranked| ranked.rank).collect()
+    }
+
+    #[test]
+    fn equal_scores_are_ordered_by_time_then_steamid() {
+        let mut board = Board::default();
+        board.insert(entry(5, 100, 2000));
+        board.insert(entry(3, 100, 1000));
+        board.insert(entry(4, 100, 1000));
+        board.insert(entry(9, 200, 3000));
+        board.insert(entry(1, 100, 2000));
+
+        let order: Vec<u64> = board.entries.iter().map(|entry| entry.steam_id).collect();
+        assert_eq!(order, vec![9, 3, 4, 1, 5]);
+    }
+
+    #[test]
+    fn registering_the_same_score_keeps_the_place_among_ties() {
+        let store = store();
+        store
+            .boards
+            .write()
+            .entry(0)
+            .or_default()
+            .insert(entry(2, 100, 0));
+
+        // Player 1 reached the score later, so ranks below despite the lower steamid.
+        assert_eq!(store.register(0, SteamId::new(1), 100, vec![]), Ok(2));
+        assert_eq!(
+            store.register(0, SteamId::new(1), 100, b"new".to_vec()),
+            Ok(2)
+        );
+        assert_eq!(store.register(0, SteamId::new(1), 101, vec![]), Ok(1));
+        assert_eq!(store.rank(0, SteamId::new(2)), Some(2));
+        assert_eq!(store.count(0), 2);
+    }
+
+    #[test]
+    fn register_rejects_unknown_boards_and_large_data() {
+        let store = store();
+
+        assert_eq!(
+            store.register(4, SteamId::new(1), 1, vec![]),
+            Err(RankingError::UnknownBoard(4))
+        );
+        assert_eq!(
+            store.register(0, SteamId::new(1), 1, vec![0; 17]),
+            Err(RankingError::DataTooLarge(17))
+        );
+        assert_eq!(store.count(0), 0);
+    }
+
+    #[test]
+    fn around_rank_is_centred_in_the_middle_of_a_board() {
+        let store = board_of(20);
+
+        assert_eq!(ranks(&store.around_rank(0, 10, 5)), vec![8, 9, 10, 11, 12]);
+        assert_eq!(store.around_rank(0, 10, 5)[2].entry.steam_id, 10);
+    }
+
+    #[test]
+    fn around_rank_near_the_top_starts_at_first() {
+        let store = board_of(20);
+
+        assert_eq!(ranks(&store.around_rank(0, 1, 5)), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ranks(&store.around_rank(0, 2, 5)), vec![1, 2, 3, 4, 5]);
+        // Rank 0 doesn't exist and is treated as the top.
+        assert_eq!(ranks(&store.around_rank(0, 0, 5)), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn around_rank_near_the_bottom_ends_at_last() {
+        let store = board_of(20);
+
+        assert_eq!(
+            ranks(&store.around_rank(0, 20, 5)),
+            vec![16, 17, 18, 19, 20]
+        );
+        assert_eq!(
+            ranks(&store.around_rank(0, 19, 5)),
+            vec![16, 17, 18, 19, 20]
+        );
+        assert_eq!(
+            ranks(&store.around_rank(0, 500, 5)),
+            vec![16, 17, 18, 19, 20]
+        );
+    }
+
+    #[test]
+    fn around_rank_on_small_or_missing_boards() {
+        let store = board_of(3);
+
+        assert_eq!(ranks(&store.around_rank(0, 2, 5)), vec![1, 2, 3]);
+        assert!(store.around_rank(1, 1, 5).is_empty());
+        assert!(store.around_rank(0, 1, 0).is_empty());
+    }
+}
@@ -0,0 +1,706 @@
+//! The level range rules every multiplayer feature matches players with.
+//!
+//! Who can meet whom is decided by soul level, the highest weapon upgrade level each player has
+//! held and an optional multiplayer password. The soul level range depends on the item used to
+//! start the meeting, so each [MatchingItem] has its own [SoulLevelRange]. The weapon level table
+//! is shared by all of them. Any of the default tables can be overridden from the `matchmaking`
+//! section of the config file.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::{read_int, ConfigError};
+
+/// The highest regular weapon upgrade level. Levels above this are treated as this.
+pub const MAX_WEAPON_LEVEL: u32 = 10;
+
+const WEAPON_LEVEL_COUNT: usize = MAX_WEAPON_LEVEL as usize + 1;
+
+/// The lowest weapon level each weapon level can be matched with, indexed by weapon level.
+const DEFAULT_WEAPON_LEVEL_LOWER: [u32; WEAPON_LEVEL_COUNT] = [0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 7];
+
+/// The highest weapon level each weapon level can be matched with, indexed by weapon level.
+const DEFAULT_WEAPON_LEVEL_UPPER: [u32; WEAPON_LEVEL_COUNT] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 10];
+
+/// What a player is matched on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MatchingParameters {
+    pub soul_level: u32,
+    /// The highest upgrade level of any weapon the player has held.
+    pub weapon_level: u32,
+    pub password: Option<String>,
+}
+
+/// The ways players end up in each other's worlds. Each is matched from the point of view of one
+/// of the two players, called the origin below, whose levels the range is worked out from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchingItem {
+    /// A host summoning a cooperator. The origin is the host.
+    WhiteSignSoapstone,
+    /// A host summoning a red phantom to fight. The origin is the host.
+    RedSignSoapstone,
+    /// An invader looking for a world to invade. The origin is the invader.
+    RedEyeOrb,
+    /// A covenant member summoned to defend an invaded host. The origin is the host.
+    CovenantDefender,
+    /// A covenant member summoned to hunt an intruder in the area they guard. The origin is the
+    /// player whose world they are summoned into.
+    AreaGuardian,
+    /// A player summoned to defend a boss in a host's world. The origin is the host.
+    BossDefender,
+    /// Players being grouped into an arena match. Each player has to match every other player
+    /// from both sides.
+    QuickMatch,
+}
+
+impl MatchingItem {
+    pub const ALL: [MatchingItem; 7] = [
+        MatchingItem::WhiteSignSoapstone,
+        MatchingItem::RedSignSoapstone,
+        MatchingItem::RedEyeOrb,
+        MatchingItem::CovenantDefender,
+        MatchingItem::AreaGuardian,
+        MatchingItem::BossDefender,
+        MatchingItem::QuickMatch,
+    ];
+
+    /// The name of the config table this item's soul level range can be overridden in.
+    pub fn config_key(&self) -> &'static str {
+        match self {
+            MatchingItem::WhiteSignSoapstone => "white_sign_soapstone",
+            MatchingItem::RedSignSoapstone => "red_sign_soapstone",
+            MatchingItem::RedEyeOrb => "red_eye_orb",
+            MatchingItem::CovenantDefender => "covenant_defender",
+            MatchingItem::AreaGuardian => "area_guardian",
+            MatchingItem::BossDefender => "boss_defender",
+            MatchingItem::QuickMatch => "quick_match",
+        }
+    }
+
+    fn default_soul_level_range(&self) -> SoulLevelRange {
+        match self {
+            MatchingItem::WhiteSignSoapstone
+            | MatchingItem::CovenantDefender
+            | MatchingItem::QuickMatch => SoulLevelRange::new(10, 10, 10, 10),
+            MatchingItem::RedSignSoapstone
+            | MatchingItem::AreaGuardian
+            | MatchingItem::BossDefender => SoulLevelRange::new(10, 10, 10, 0),
+            MatchingItem::RedEyeOrb => SoulLevelRange::new(10, 0, 10, 10),
+        }
+    }
+}
+
+/// The soul levels the origin player can be matched with: from their own level less
+/// `lower_percent`% of it and `lower_offset`, up to their own level plus `upper_percent`% of it and
+/// `upper_offset`. Percentages are rounded down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoulLevelRange {
+    pub lower_percent: u32,
+    pub lower_offset: u32,
+    pub upper_percent: u32,
+    pub upper_offset: u32,
+}
+
+impl SoulLevelRange {
+    pub const fn new(
+        lower_percent: u32,
+        lower_offset: u32,
+        upper_percent: u32,
+        upper_offset: u32,
+    ) -> Self {
+        Self {
+            lower_percent,
+            lower_offset,
+            upper_percent,
+            upper_offset,
+        }
+    }
+
+    /// The lowest and highest soul levels, inclusive, that `soul_level` can be matched with.
+    pub fn bounds(&self, soul_level: u32) -> (u32, u32) {
+        let lower = soul_level
+            .saturating_sub(soul_level * self.lower_percent / 100)
+            .saturating_sub(self.lower_offset)
+            .max(1);
+        let upper = soul_level
+            .saturating_add(soul_level * self.upper_percent / 100)
+            .saturating_add(self.upper_offset);
+
+        (lower, upper)
+    }
+
+    pub fn contains(&self, origin: u32, target: u32) -> bool {
+        let (lower, upper) = self.bounds(origin);
+
+        lower <= target && target <= upper
+    }
+}
+
+/// The weapon levels each weapon level can be matched with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WeaponLevelTable {
+    lower: [u32; WEAPON_LEVEL_COUNT],
+    upper: [u32; WEAPON_LEVEL_COUNT],
+}
+
+impl Default for WeaponLevelTable {
+    fn default() -> Self {
+        Self {
+            lower: DEFAULT_WEAPON_LEVEL_LOWER,
+            upper: DEFAULT_WEAPON_LEVEL_UPPER,
+        }
+    }
+}
+
+impl WeaponLevelTable {
+    /// Build a table from the lowest and highest matching level of each weapon level. Returns
+    /// `None` unless both have an entry for every level and each lower bound is at most its upper
+    /// bound.
+    pub fn new(lower: &[u32], upper: &[u32]) -> Option<Self> {
+        if lower.len() != WEAPON_LEVEL_COUNT || upper.len() != WEAPON_LEVEL_COUNT {
+            return None;
+        }
+
+        if lower.iter().zip(upper).any(|(lower, upper)| lower > upper) {
+            return None;
+        }
+
+        let mut table = Self::default();
+        table.lower.copy_from_slice(lower);
+        table.upper.copy_from_slice(upper);
+
+        Some(table)
+    }
+
+    /// The lowest and highest weapon levels, inclusive, that `weapon_level` can be matched with.
+    pub fn bounds(&self, weapon_level: u32) -> (u32, u32) {
+        let index = weapon_level.min(MAX_WEAPON_LEVEL) as usize;
+
+        (self.lower[index], self.upper[index])
+    }
+
+    pub fn contains(&self, origin: u32, target: u32) -> bool {
+        let (lower, upper) = self.bounds(origin);
+        let target = target.min(MAX_WEAPON_LEVEL);
+
+        lower <= target && target <= upper
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordMatch {
+    /// Neither player set a password, so the level ranges apply.
+    NoPassword,
+    /// Both players set the same password, so they match whatever their levels.
+    Matched,
+    /// Only one player set a password, or they set different ones, so they can't match.
+    Mismatched,
+}
+
+/// Compare two players' multiplayer passwords. An empty password is the same as not having one.
+pub fn match_passwords(a: Option<&str>, b: Option<&str>) -> PasswordMatch {
+    let a = a.filter(|password| !password.is_empty());
+    let b = b.filter(|password| !password.is_empty());
+
+    match (a, b) {
+        (None, None) => PasswordMatch::NoPassword,
+        (Some(a), Some(b)) if a == b => PasswordMatch::Matched,
+        _ => PasswordMatch::Mismatched,
+    }
+}
+
+/// The complete set of level range rules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchingRules {
+    soul_level: HashMap<MatchingItem, SoulLevelRange>,
+    weapon_level: WeaponLevelTable,
+}
+
+impl Default for MatchingRules {
+    fn default() -> Self {
+        Self {
+            soul_level: MatchingItem::ALL
+                .iter()
+                .map(|item| (*item, item.default_soul_level_range()))
+                .collect(),
+            weapon_level: Default::default(),
+        }
+    }
+}
+
+impl MatchingRules {
+    /// The default rules, with anything set in the config file's `matchmaking` section in place of
+    /// the default values.
+    pub fn load(config_file: &config::Config) -> Result<Self, ConfigError> {
+        let mut rules = Self::default();
+
+        for item in MatchingItem::ALL.iter() {
+            let default = item.default_soul_level_range();
+            let read = |field: &str, default: u32| {
+                let key = format!("matchmaking.{}.{}", item.config_key(), field);
+
+                match read_int(config_file, &key)? {
+                    Some(value) => u32::try_from(value)
+                        .map_err(|_| ConfigError::invalid(&key, "a whole number, not below zero")),
+                    None => Ok(default),
+                }
+            };
+
+            let range = SoulLevelRange::new(
+                read("lower_percent", default.lower_percent)?,
+                read("lower_offset", default.lower_offset)?,
+                read("upper_percent", default.upper_percent)?,
+                read("upper_offset", default.upper_offset)?,
+            );
+            rules.soul_level.insert(*item, range);
+        }
+
+        let read_levels = |key: &str, default: &[u32; WEAPON_LEVEL_COUNT]| {
+            let values = match config_file.get_array(key) {
+                Ok(values) => values,
+                Err(config::ConfigError::NotFound(_)) => return Ok(default.to_vec()),
+                Err(_) => return Err(ConfigError::invalid(key, "a list of weapon levels")),
+            };
+
+            values
+                .into_iter()
+                .map(|value| {
+                    value
+                        .into_int()
+                        .ok()
+                        .and_then(|level| u32::try_from(level).ok())
+                        .ok_or_else(|| ConfigError::invalid(key, "a list of weapon levels"))
+                })
+                .collect::<Result<Vec<u32>, _>>()
+        };
+
+        let lower = read_levels("matchmaking.weapon_level_lower", &rules.weapon_level.lower)?;
+        let upper = read_levels("matchmaking.weapon_level_upper", &rules.weapon_level.upper)?;
+
+        rules.weapon_level = WeaponLevelTable::new(&lower, &upper).ok_or_else(|| {
+            ConfigError::invalid(
+                "matchmaking.weapon_level_lower",
+                format!(
+                    "{} entries like matchmaking.weapon_level_upper, with none above its upper \
+                     bound",
+                    WEAPON_LEVEL_COUNT
+                ),
+            )
+        })?;
+
+        Ok(rules)
+    }
+
+    pub fn soul_level_range(&self, item: MatchingItem) -> SoulLevelRange {
+        self.soul_level[&item]
+    }
+
+    pub fn weapon_levels(&self) -> &WeaponLevelTable {
+        &self.weapon_level
+    }
+
+    /// Whether `target` can be matched with `origin` through `item`. Players who share a password
+    /// match regardless of level, and a player with a password never matches one without.
+    pub fn can_match(
+        &self,
+        item: MatchingItem,
+        origin: &MatchingParameters,
+        target: &MatchingParameters,
+    ) -> bool {
+        match match_passwords(origin.password.as_deref(), target.password.as_deref()) {
+            PasswordMatch::Matched => true,
+            PasswordMatch::Mismatched => false,
+            PasswordMatch::NoPassword => {
+                self.soul_level_range(item)
+                    .contains(origin.soul_level, target.soul_level)
+                    && self
+                        .weapon_level
+                        .contains(origin.weapon_level, target.weapon_level)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(soul_level: u32, weapon_level: u32) -> MatchingParameters {
+        MatchingParameters {
+            soul_level,
+            weapon_level,
+            password: None,
+        }
+    }
+
+    fn with_password(soul_level: u32, weapon_level: u32, password: &str) -> MatchingParameters {
+        MatchingParameters {
+            password: Some(password.to_string()),
+            ..player(soul_level, weapon_level)
+        }
+    }
+
+    #[test]
+    fn white_sign_range_is_ten_levels_and_ten_percent_either_way() {
+        let range = MatchingRules::default().soul_level_range(MatchingItem::WhiteSignSoapstone);
+
+        assert_eq!(range.bounds(1), (1, 11));
+        assert_eq!(range.bounds(10), (1, 21));
+        assert_eq!(range.bounds(50), (35, 65));
+        assert_eq!(range.bounds(100), (80, 120));
+        assert_eq!(range.bounds(120), (98, 142));
+        assert_eq!(range.bounds(802), (712, 892));
+    }
+
+    #[test]
+    fn red_sign_range_reaches_further_down_than_up() {
+        let range = MatchingRules::default().soul_level_range(MatchingItem::RedSignSoapstone);
+
+        assert_eq!(range.bounds(50), (35, 55));
+        assert_eq!(range.bounds(100), (80, 110));
+        assert_eq!(range.bounds(120), (98, 132));
+    }
+
+    #[test]
+    fn red_eye_orb_range_reaches_further_up_than_down() {
+        let range = MatchingRules::default().soul_level_range(MatchingItem::RedEyeOrb);
+
+        assert_eq!(range.bounds(50), (45, 65));
+        assert_eq!(range.bounds(100), (90, 120));
+        assert_eq!(range.bounds(120), (108, 142));
+    }
+
+    #[test]
+    fn invader_reaches_host_who_would_take_their_red_sign() {
+        let rules = MatchingRules::default();
+        let host = player(100, 10);
+        let invader = player(110, 10);
+
+        assert!(rules.can_match(MatchingItem::RedEyeOrb, &invader, &host));
+        assert!(rules.can_match(MatchingItem::RedSignSoapstone, &host, &invader));
+    }
+
+    #[test]
+    fn covenant_and_guardian_ranges_follow_the_soapstones() {
+        let rules = MatchingRules::default();
+
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::CovenantDefender),
+            rules.soul_level_range(MatchingItem::WhiteSignSoapstone)
+        );
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::AreaGuardian),
+            rules.soul_level_range(MatchingItem::RedSignSoapstone)
+        );
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::BossDefender),
+            rules.soul_level_range(MatchingItem::RedSignSoapstone)
+        );
+    }
+
+    #[test]
+    fn quick_match_range_is_the_same_both_ways() {
+        let rules = MatchingRules::default();
+        let a = player(100, 10);
+        let b = player(120, 10);
+
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::QuickMatch),
+            rules.soul_level_range(MatchingItem::WhiteSignSoapstone)
+        );
+        assert!(rules.can_match(MatchingItem::QuickMatch, &a, &b));
+        assert!(rules.can_match(MatchingItem::QuickMatch, &b, &a));
+    }
+
+    #[test]
+    fn soul_level_bounds_never_drop_below_one() {
+        for item in MatchingItem::ALL.iter() {
+            let range = MatchingRules::default().soul_level_range(*item);
+
+            for soul_level in 0..=20 {
+                assert!(
+                    range.bounds(soul_level).0 >= 1,
+                    "{:?} at {}",
+                    item,
+                    soul_level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn soul_level_bounds_contain_own_level_and_grow_with_it() {
+        for item in MatchingItem::ALL.iter() {
+            let range = MatchingRules::default().soul_level_range(*item);
+            let mut previous = range.bounds(1);
+
+            for soul_level in 1..=802 {
+                let (lower, upper) = range.bounds(soul_level);
+
+                assert!(lower <= soul_level && soul_level <= upper);
+                assert!(lower >= previous.0 && upper >= previous.1);
+
+                previous = (lower, upper);
+            }
+        }
+    }
+
+    #[test]
+    fn soul_level_bounds_do_not_overflow() {
+        let range = SoulLevelRange::new(100, u32::MAX, 100, u32::MAX);
+
+        assert_eq!(range.bounds(1_000), (1, u32::MAX));
+    }
+
+    #[test]
+    fn contains_is_inclusive() {
+        let range = MatchingRules::default().soul_level_range(MatchingItem::WhiteSignSoapstone);
+
+        assert!(range.contains(100, 80));
+        assert!(range.contains(100, 120));
+        assert!(!range.contains(100, 79));
+        assert!(!range.contains(100, 121));
+    }
+
+    #[test]
+    fn default_weapon_level_table() {
+        let table = WeaponLevelTable::default();
+        let expected = [
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (1, 4),
+            (2, 5),
+            (3, 6),
+            (4, 7),
+            (5, 8),
+            (6, 9),
+            (7, 10),
+            (7, 10),
+        ];
+
+        for (level, bounds) in expected.iter().enumerate() {
+            assert_eq!(
+                table.bounds(level as u32),
+                *bounds,
+                "weapon level {}",
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn weapon_levels_above_the_maximum_are_clamped() {
+        let table = WeaponLevelTable::default();
+
+        assert_eq!(table.bounds(11), table.bounds(MAX_WEAPON_LEVEL));
+        assert_eq!(table.bounds(u32::MAX), table.bounds(MAX_WEAPON_LEVEL));
+        assert!(table.contains(15, 10));
+        assert!(table.contains(10, 15));
+    }
+
+    #[test]
+    fn every_weapon_level_matches_itself() {
+        let table = WeaponLevelTable::default();
+
+        for level in 0..=MAX_WEAPON_LEVEL {
+            assert!(table.contains(level, level), "weapon level {}", level);
+        }
+    }
+
+    #[test]
+    fn weapon_level_contains_is_inclusive() {
+        let table = WeaponLevelTable::default();
+
+        assert!(table.contains(5, 3));
+        assert!(table.contains(5, 6));
+        assert!(!table.contains(5, 2));
+        assert!(!table.contains(5, 7));
+    }
+
+    #[test]
+    fn weapon_level_table_rejects_bad_input() {
+        let levels: Vec<u32> = (0..=MAX_WEAPON_LEVEL).collect();
+
+        assert!(WeaponLevelTable::new(&levels, &levels).is_some());
+        assert!(WeaponLevelTable::new(&levels[..10], &levels).is_none());
+        assert!(WeaponLevelTable::new(&levels, &levels[..10]).is_none());
+
+        let mut inverted = levels.clone();
+        inverted[3] = 4;
+        assert!(WeaponLevelTable::new(&inverted, &levels).is_none());
+    }
+
+    #[test]
+    fn passwords() {
+        assert_eq!(match_passwords(None, None), PasswordMatch::NoPassword);
+        assert_eq!(match_passwords(Some(""), None), PasswordMatch::NoPassword);
+        assert_eq!(
+            match_passwords(Some(""), Some("")),
+            PasswordMatch::NoPassword
+        );
+        assert_eq!(
+            match_passwords(Some("a"), Some("a")),
+            PasswordMatch::Matched
+        );
+        assert_eq!(
+            match_passwords(Some("a"), Some("b")),
+            PasswordMatch::Mismatched
+        );
+        assert_eq!(match_passwords(Some("a"), None), PasswordMatch::Mismatched);
+        assert_eq!(match_passwords(None, Some("a")), PasswordMatch::Mismatched);
+        assert_eq!(
+            match_passwords(Some("a"), Some("")),
+            PasswordMatch::Mismatched
+        );
+        assert_eq!(
+            match_passwords(Some("a"), Some("A")),
+            PasswordMatch::Mismatched
+        );
+    }
+
+    #[test]
+    fn can_match_checks_soul_and_weapon_levels() {
+        let rules = MatchingRules::default();
+        let item = MatchingItem::WhiteSignSoapstone;
+
+        assert!(rules.can_match(item, &player(100, 5), &player(120, 6)));
+        assert!(!rules.can_match(item, &player(100, 5), &player(121, 6)));
+        assert!(!rules.can_match(item, &player(100, 5), &player(120, 7)));
+        assert!(!rules.can_match(item, &player(100, 5), &player(79, 5)));
+    }
+
+    #[test]
+    fn can_match_uses_the_items_range() {
+        let rules = MatchingRules::default();
+        let host = player(100, 10);
+        let phantom = player(115, 10);
+
+        assert!(rules.can_match(MatchingItem::WhiteSignSoapstone, &host, &phantom));
+        assert!(!rules.can_match(MatchingItem::RedSignSoapstone, &host, &phantom));
+    }
+
+    #[test]
+    fn shared_password_bypasses_level_ranges() {
+        let rules = MatchingRules::default();
+
+        for item in MatchingItem::ALL.iter() {
+            assert!(rules.can_match(
+                *item,
+                &with_password(10, 0, "fashion"),
+                &with_password(700, 10, "fashion")
+            ));
+        }
+    }
+
+    #[test]
+    fn password_holders_only_match_each_other() {
+        let rules = MatchingRules::default();
+
+        for item in MatchingItem::ALL.iter() {
+            assert!(!rules.can_match(*item, &with_password(100, 5, "a"), &player(100, 5)));
+            assert!(!rules.can_match(*item, &player(100, 5), &with_password(100, 5, "a")));
+            assert!(!rules.can_match(
+                *item,
+                &with_password(100, 5, "a"),
+                &with_password(100, 5, "b")
+            ));
+        }
+    }
+
+    #[test]
+    fn every_item_has_a_distinct_config_key() {
+        let mut keys: Vec<_> = MatchingItem::ALL
+            .iter()
+            .map(|item| item.config_key())
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        assert_eq!(keys.len(), MatchingItem::ALL.len());
+    }
+
+    #[test]
+    fn load_without_overrides_gives_the_default_rules() {
+        let config_file = config::Config::default();
+
+        assert_eq!(
+            MatchingRules::load(&config_file).unwrap(),
+            MatchingRules::default()
+        );
+    }
+
+    #[test]
+    fn load_overrides_soul_level_ranges() {
+        let mut config_file = config::Config::default();
+        config_file
+            .set("matchmaking.red_eye_orb.lower_percent", 20i64)
+            .unwrap()
+            .set("matchmaking.red_eye_orb.upper_offset", 5i64)
+            .unwrap();
+
+        let rules = MatchingRules::load(&config_file).unwrap();
+
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::RedEyeOrb),
+            SoulLevelRange::new(20, 0, 10, 5)
+        );
+        assert_eq!(
+            rules.soul_level_range(MatchingItem::WhiteSignSoapstone),
+            MatchingRules::default().soul_level_range(MatchingItem::WhiteSignSoapstone)
+        );
+    }
+
+    #[test]
+    fn load_overrides_the_weapon_level_table() {
+        let levels: Vec<i64> = (0..=MAX_WEAPON_LEVEL as i64).collect();
+        let mut config_file = config::Config::default();
+        config_file
+            .set("matchmaking.weapon_level_lower", levels.clone())
+            .unwrap()
+            .set("matchmaking.weapon_level_upper", levels)
+            .unwrap();
+
+        let table = MatchingRules::load(&config_file)
+            .unwrap()
+            .weapon_levels()
+            .clone();
+
+        for level in 0..=MAX_WEAPON_LEVEL {
+            assert_eq!(table.bounds(level), (level, level));
+        }
+    }
+
+    #[test]
+    fn load_rejects_a_short_weapon_level_table() {
+        let mut config_file = config::Config::default();
+        config_file
+            .set("matchmaking.weapon_level_lower", vec![0i64, 1, 2])
+            .unwrap();
+
+        assert!(MatchingRules::load(&config_file).is_err());
+    }
+
+    #[test]
+    fn load_rejects_weapon_levels_that_arent_levels() {
+        let mut config_file = config::Config::default();
+        config_file
+            .set(
+                "matchmaking.weapon_level_upper",
+                vec!["high"; WEAPON_LEVEL_COUNT],
+            )
+            .unwrap();
+
+        assert!(MatchingRules::load(&config_file).is_err());
+    }
+
+    #[test]
+    fn load_rejects_negative_soul_level_ranges() {
+        let mut config_file = config::Config::default();
+        config_file
+            .set("matchmaking.white_sign_soapstone.lower_offset", -10i64)
+            .unwrap();
+
+        assert!(MatchingRules::load(&config_file).is_err());
+    }
+}
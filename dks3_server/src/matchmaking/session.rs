@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use prost::Message;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+
+use dks3_proto::msg::MessageType;
+
+use crate::matchmaking::invasion::Invasion;
+use crate::matchmaking::player_status::PlayerStatus;
+use crate::net::ConnectionId;
+
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// How long a player has to connect to the game port after being issued a login token.
+const GAME_TICKET_TTL_SECS: i64 = 60;
+
+#[derive(Debug, Error)]
+#[error("invalid steamid")]
+pub struct InvalidSteamId;
+
+/// A 64-bit Steam ID. The client sends these as a 16 character hex string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SteamId(u64);
+
+impl SteamId {
+    pub fn new(id: u64) -> Self {
+        SteamId(id)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for SteamId {
+    type Err = InvalidSteamId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The client pads the string with a trailing NUL in some requests.
+        let s = s.trim_end_matches('\0');
+
+        u64::from_str_radix(s, 16)
+            .map(SteamId)
+            .map_err(|_| InvalidSteamId)
+    }
+}
+
+impl Display for SteamId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Service {
+    Login,
+    Auth,
+    Game,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServiceConnections {
+    pub login: Option<ConnectionId>,
+    pub auth: Option<ConnectionId>,
+    pub game: Option<ConnectionId>,
+}
+
+impl ServiceConnections {
+    fn slot(&mut self, service: Service) -> &mut Option<ConnectionId> {
+        match service {
+            Service::Login => &mut self.login,
+            Service::Auth => &mut self.auth,
+            Service::Game => &mut self.game,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.login.is_none() && self.auth.is_none() && self.game.is_none()
+    }
+}
+
+/// A message sent to a player's game connection without them having requested it.
+#[derive(Debug)]
+pub struct Push {
+    pub msg_type: MessageType,
+    pub data: BytesMut,
+}
+
+/// Issued by the auth service and redeemed when the player connects to the game port.
+#[derive(Clone, Debug)]
+pub struct GameTicket {
+    pub steam_id: SteamId,
+    pub session_key: [u8; 16],
+    issued_at: DateTime<Utc>,
+}
+
+/// The player's connection to the game port, once they have made one.
+#[derive(Clone, Debug)]
+pub struct GameSession {
+    pub connection: ConnectionId,
+    pub started_at: DateTime<Utc>,
+    pushes: mpsc::UnboundedSender<Push>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub steam_id: SteamId,
+    pub connections: ServiceConnections,
+    pub client_version: Option<i64>,
+    pub logged_in_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub game: Option<GameSession>,
+    pub status: PlayerStatus,
+    /// The invader in the player's world, or on their way there.
+    pub invasion: Option<Invasion>,
+}
+
+#[derive(Clone, Debug)]
+pub enum SessionEvent {
+    /// The player opened their first connection to any service.
+    Connected(SteamId),
+
+    /// The player's last connection closed and their session was removed.
+    Disconnected(Session),
+}
+
+#[derive(Default, Debug)]
+struct SessionState {
+    sessions: HashMap<SteamId, Session>,
+    /// The players in each area, so searches by area don't have to look at everyone.
+    areas: HashMap<u32, HashSet<SteamId>>,
+    game_tickets: HashMap<u64, GameTicket>,
+}
+
+impl SessionState {
+    fn move_area(&mut self, steam_id: SteamId, from: Option<u32>, to: Option<u32>) {
+        if from == to {
+            return;
+        }
+
+        if let Some(from) = from {
+            if let Some(players) = self.areas.get_mut(&from) {
+                players.remove(&steam_id);
+
+                if players.is_empty() {
+                    self.areas.remove(&from);
+                }
+            }
+        }
+
+        if let Some(to) = to {
+            self.areas.entry(to).or_default().insert(steam_id);
+        }
+    }
+}
+
+/// Every player with an open connection to one of our services.
+#[derive(Clone, Debug)]
+pub struct SessionRegistry {
+    state: Arc<RwLock<SessionState>>,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        Self {
+            state: Default::default(),
+            events,
+        }
+    }
+}
+
+impl SessionRegistry {
+    /// Record that `connection` to `service` belongs to `steam_id`. The connection is removed from
+    /// the registry when the returned [SessionRegistration] is dropped.
+    pub fn register(
+        &self,
+        steam_id: SteamId,
+        service: Service,
+        connection: ConnectionId,
+        client_version: Option<i64>,
+    ) -> SessionRegistration {
+        self.register_with(steam_id, service, connection, client_version, None)
+    }
+
+    /// Register the player's game connection. Messages passed to [SessionRegistry::push] for this
+    /// player are delivered to `pushes`.
+    pub fn register_game(
+        &self,
+        steam_id: SteamId,
+        connection: ConnectionId,
+        pushes: mpsc::UnboundedSender<Push>,
+    ) -> SessionRegistration {
+        self.register_with(steam_id, Service::Game, connection, None, Some(pushes))
+    }
+
+    fn register_with(
+        &self,
+        steam_id: SteamId,
+        service: Service,
+        connection: ConnectionId,
+        client_version: Option<i64>,
+        pushes: Option<mpsc::UnboundedSender<Push>>,
+    ) -> SessionRegistration {
+        let now = Utc::now();
+        let mut state = self.state.write();
+        let mut created = false;
+
+        let session = state.sessions.entry(steam_id).or_insert_with(|| {
+            created = true;
+
+            Session {
+                steam_id,
+                connections: Default::default(),
+                client_version: None,
+                logged_in_at: now,
+                last_activity: now,
+                game: None,
+                status: Default::default(),
+                invasion: None,
+            }
+        });
+
+        *session.connections.slot(service) = Some(connection);
+        session.last_activity = now;
+
+        if client_version.is_some() {
+            session.client_version = client_version;
+        }
+
+        if let Some(pushes) = pushes {
+            session.game = Some(GameSession {
+                connection,
+                started_at: now,
+                pushes,
+            });
+        }
+
+        drop(state);
+
+        if created {
+            let _ = self.events.send(SessionEvent::Connected(steam_id));
+        }
+
+        SessionRegistration {
+            registry: self.clone(),
+            steam_id,
+            service,
+            connection,
+        }
+    }
+
+    fn unregister(&self, steam_id: SteamId, service: Service, connection: ConnectionId) {
+        let mut state = self.state.write();
+
+        let session = match state.sessions.get_mut(&steam_id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        // A newer connection to the same service may have replaced this one already.
+        let slot = session.connections.slot(service);
+        if *slot != Some(connection) {
+            return;
+        }
+
+        *slot = None;
+
+        if service == Service::Game {
+            session.game = None;
+        }
+
+        if session.connections.is_empty() {
+            let session = state.sessions.remove(&steam_id);
+            if let Some(session) = &session {
+                state.move_area(steam_id, session.status.online_area_id, None);
+            }
+            drop(state);
+
+            if let Some(session) = session {
+                let _ = self.events.send(SessionEvent::Disconnected(session));
+            }
+        }
+    }
+
+    /// Remember `login_token` so the player can later redeem it on the game port.
+    pub fn issue_game_ticket(&self, steam_id: SteamId, login_token: u64, session_key: [u8; 16]) {
+        let now = Utc::now();
+        let mut state = self.state.write();
+
+        state
+            .game_tickets
+            .retain(|_, ticket| now - ticket.issued_at < Duration::seconds(GAME_TICKET_TTL_SECS));
+        state.game_tickets.insert(
+            login_token,
+            GameTicket {
+                steam_id,
+                session_key,
+                issued_at: now,
+            },
+        );
+    }
+
+    /// Redeem a login token issued by [SessionRegistry::issue_game_ticket]. Each token can only be
+    /// used once.
+    pub fn claim_game_ticket(&self, login_token: u64) -> Option<GameTicket> {
+        let ticket = self.state.write().game_tickets.remove(&login_token)?;
+
+        if Utc::now() - ticket.issued_at < Duration::seconds(GAME_TICKET_TTL_SECS) {
+            Some(ticket)
+        } else {
+            None
+        }
+    }
+
+    /// Send `message` to the player's game connection. Returns false if they don't have one.
+    pub fn push<M: Message>(&self, steam_id: SteamId, msg_type: MessageType, message: &M) -> bool {
+        let state = self.state.read();
+        let game = match state.sessions.get(&steam_id).and_then(|s| s.game.as_ref()) {
+            Some(game) => game,
+            None => return false,
+        };
+
+        let mut data = BytesMut::with_capacity(message.encoded_len());
+        message
+            .encode(&mut data)
+            .expect("Unable to encode push message");
+
+        game.pushes.send(Push { msg_type, data }).is_ok()
+    }
+
+    /// Change the player's session with `f`, returning what it returns. Returns `None` if they
+    /// aren't online.
+    pub fn update<R, F: FnOnce(&mut Session) -> R>(&self, steam_id: SteamId, f: F) -> Option<R> {
+        let mut state = self.state.write();
+        let session = state.sessions.get_mut(&steam_id)?;
+
+        let from = session.status.online_area_id;
+        let result = f(session);
+        let to = session.status.online_area_id;
+
+        state.move_area(steam_id, from, to);
+
+        Some(result)
+    }
+
+    /// Note that the player has just done something, for idle tracking.
+    pub fn touch(&self, steam_id: SteamId) {
+        if let Some(session) = self.state.write().sessions.get_mut(&steam_id) {
+            session.last_activity = Utc::now();
+        }
+    }
+
+    pub fn is_online(&self, steam_id: SteamId) -> bool {
+        self.state.read().sessions.contains_key(&steam_id)
+    }
+
+    pub fn get(&self, steam_id: SteamId) -> Option<Session> {
+        self.state.read().sessions.get(&steam_id).cloned()
+    }
+
+    /// Every session `filter` accepts.
+    pub fn find<F: FnMut(&Session) -> bool>(&self, mut filter: F) -> Vec<Session> {
+        self.state
+            .read()
+            .sessions
+            .values()
+            .filter(|session| filter(session))
+            .cloned()
+            .collect()
+    }
+
+    /// Every session in `online_area_id` that `filter` accepts. Only players in the area are
+    /// looked at.
+    pub fn find_in_area<F: FnMut(&Session) -> bool>(
+        &self,
+        online_area_id: u32,
+        mut filter: F,
+    ) -> Vec<Session> {
+        let state = self.state.read();
+
+        state
+            .areas
+            .get(&online_area_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|steam_id| state.sessions.get(steam_id))
+            .filter(|session| filter(session))
+            .cloned()
+            .collect()
+    }
+
+    pub fn list(&self) -> Vec<Session> {
+        self.state.read().sessions.values().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.read().sessions.len()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+}
+
+/// Keeps a connection listed against its player's session for as long as it is held.
+#[derive(Debug)]
+pub struct SessionRegistration {
+    registry: SessionRegistry,
+    steam_id: SteamId,
+    service: Service,
+    connection: ConnectionId,
+}
+
+impl SessionRegistration {
+    pub fn steam_id(&self) -> SteamId {
+        self.steam_id
+    }
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        self.registry
+            .unregister(self.steam_id, self.service, self.connection);
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::matchmaking::rules::{MatchingItem, MatchingParameters, MatchingRules};
+use crate::matchmaking::session::SteamId;
+use crate::matchmaking::world::{JoinError, PhantomRole, WorldStore};
+
+/// How long a sign owner has to answer a summon before the sign is offered to other hosts again.
+const SUMMON_TIMEOUT_SECS: i64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SignType {
+    /// Placed with the White Sign Soapstone, to help the host through their world.
+    White,
+    /// Placed with the Red Sign Soapstone, to fight the host.
+    Red,
+}
+
+impl TryFrom<u32> for SignType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SignType::White),
+            1 => Ok(SignType::Red),
+            _ => Err(value),
+        }
+    }
+}
+
+impl SignType {
+    /// The item the sign was placed with, which decides who can see it.
+    pub fn matching_item(&self) -> MatchingItem {
+        match self {
+            SignType::White => MatchingItem::WhiteSignSoapstone,
+            SignType::Red => MatchingItem::RedSignSoapstone,
+        }
+    }
+}
+
+impl From<SignType> for u32 {
+    fn from(sign_type: SignType) -> Self {
+        match sign_type {
+            SignType::White => 0,
+            SignType::Red => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SummonSign {
+    pub id: u64,
+    pub owner: SteamId,
+    pub sign_type: SignType,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    pub matching: MatchingParameters,
+    /// Where the sign sits in the world, which only the client understands.
+    pub data: Bytes,
+    pub created_at: DateTime<Utc>,
+    /// The host waiting to hear back from the owner, if someone has touched the sign, and when
+    /// they started waiting.
+    pub summoned_by: Option<(SteamId, DateTime<Utc>)>,
+}
+
+impl SummonSign {
+    /// The host waiting to hear back from the owner, if there is one.
+    pub fn waiting_host(&self) -> Option<SteamId> {
+        self.summoned_by.map(|(host, _)| host)
+    }
+
+    /// Whether the sign can be offered to a host, because no one is waiting on it or the host
+    /// waiting on it has given up.
+    fn is_available(&self, now: DateTime<Utc>) -> bool {
+        self.summoned_by.map_or(true, |(_, summoned_at)| {
+            now - summoned_at > Duration::seconds(SUMMON_TIMEOUT_SECS)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NewSummonSign {
+    pub owner: SteamId,
+    pub sign_type: SignType,
+    pub online_area_id: u32,
+    pub cell_id: u32,
+    pub matching: MatchingParameters,
+    pub data: Bytes,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum SummonError {
+    #[error("sign does not exist")]
+    NotFound,
+
+    #[error("players can't summon themselves")]
+    OwnSign,
+
+    #[error("host doesn't match the sign")]
+    NotMatched,
+
+    #[error("sign owner is already being summoned")]
+    Busy,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum AcceptError {
+    #[error("sign has no summon waiting on it")]
+    NotPending,
+
+    #[error("owner can't join {host}'s world: {error}")]
+    CantJoin { host: SteamId, error: JoinError },
+}
+
+#[derive(Default, Debug)]
+struct SignState {
+    next_id: u64,
+    signs: HashMap<u64, SummonSign>,
+    /// Sign IDs in each online area.
+    areas: HashMap<u32, Vec<u64>>,
+    /// The signs each player has down. There is at most one of each type.
+    owners: HashMap<SteamId, Vec<u64>>,
+}
+
+impl SignState {
+    fn remove(&mut self, id: u64) -> Option<SummonSign> {
+        let sign = self.signs.remove(&id)?;
+
+        if let Some(area) = self.areas.get_mut(&sign.online_area_id) {
+            area.retain(|&other| other != id);
+
+            if area.is_empty() {
+                self.areas.remove(&sign.online_area_id);
+            }
+        }
+
+        if let Some(owned) = self.owners.get_mut(&sign.owner) {
+            owned.retain(|&other| other != id);
+
+            if owned.is_empty() {
+                self.owners.remove(&sign.owner);
+            }
+        }
+
+        Some(sign)
+    }
+
+    fn remove_all(&mut self, owner: SteamId) -> Vec<SummonSign> {
+        self.owners
+            .remove(&owner)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| self.remove(id))
+            .collect()
+    }
+}
+
+/// Summon signs players have put down, and the summons in progress on them.
+#[derive(Clone, Debug, Default)]
+pub struct SignStore {
+    state: Arc<RwLock<SignState>>,
+}
+
+impl SignStore {
+    /// Put a sign down. A player can only have one sign of each type down, so any earlier sign of
+    /// the same type is returned after being removed.
+    pub fn create(&self, sign: NewSummonSign) -> (u64, Option<SummonSign>) {
+        let mut state = self.state.write();
+
+        let previous = state
+            .owners
+            .get(&sign.owner)
+            .into_iter()
+            .flatten()
+            .copied()
+            .find(|id| {
+                state
+                    .signs
+                    .get(id)
+                    .map_or(false, |other| other.sign_type == sign.sign_type)
+            });
+        let replaced = previous.and_then(|id| state.remove(id));
+
+        state.next_id += 1;
+        let id = state.next_id;
+
+        state.areas.entry(sign.online_area_id).or_default().push(id);
+        state.owners.entry(sign.owner).or_default().push(id);
+        state.signs.insert(
+            id,
+            SummonSign {
+                id,
+                owner: sign.owner,
+                sign_type: sign.sign_type,
+                online_area_id: sign.online_area_id,
+                cell_id: sign.cell_id,
+                matching: sign.matching,
+                data: sign.data,
+                created_at: Utc::now(),
+                summoned_by: None,
+            },
+        );
+
+        (id, replaced)
+    }
+
+    /// Refresh the matching parameters on one of `owner`'s signs, which change as they level up.
+    pub fn update(&self, owner: SteamId, id: u64, matching: MatchingParameters) -> bool {
+        match self.state.write().signs.get_mut(&id) {
+            Some(sign) if sign.owner == owner => {
+                sign.matching = matching;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove a sign on behalf of `owner`, returning it if it was theirs.
+    pub fn remove(&self, owner: SteamId, id: u64) -> Option<SummonSign> {
+        let mut state = self.state.write();
+
+        match state.signs.get(&id) {
+            Some(sign) if sign.owner == owner => state.remove(id),
+            _ => None,
+        }
+    }
+
+    /// Remove every sign `owner` has down, returning them.
+    pub fn remove_all(&self, owner: SteamId) -> Vec<SummonSign> {
+        self.state.write().remove_all(owner)
+    }
+
+    /// Pick up to `count` signs from `online_area_id` at random that a host with `matching` can
+    /// use under `rules`. The requester's own signs, and signs with a summon still in progress,
+    /// are left out.
+    pub fn list<R: Rng>(
+        &self,
+        rules: &MatchingRules,
+        requester: SteamId,
+        matching: &MatchingParameters,
+        online_area_id: u32,
+        count: usize,
+        rng: &mut R,
+    ) -> Vec<SummonSign> {
+        let now = Utc::now();
+        let state = self.state.read();
+
+        state
+            .areas
+            .get(&online_area_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.signs.get(id))
+            .filter(|sign| sign.owner != requester && sign.is_available(now))
+            .filter(|sign| {
+                rules.can_match(sign.sign_type.matching_item(), matching, &sign.matching)
+            })
+            .choose_multiple(rng, count)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Start summoning the owner of a sign into `host`'s world, if a host with `matching` could
+    /// have been shown the sign under `rules`. The sign is held for `host` until the owner accepts
+    /// or rejects, or the summon times out. Returns the sign along with the host whose summon
+    /// timed out, if this one takes its place.
+    pub fn summon(
+        &self,
+        rules: &MatchingRules,
+        host: SteamId,
+        matching: &MatchingParameters,
+        id: u64,
+    ) -> Result<(SummonSign, Option<SteamId>), SummonError> {
+        let now = Utc::now();
+        let mut state = self.state.write();
+        let sign = state.signs.get_mut(&id).ok_or(SummonError::NotFound)?;
+
+        if sign.owner == host {
+            return Err(SummonError::OwnSign);
+        }
+
+        if !rules.can_match(sign.sign_type.matching_item(), matching, &sign.matching) {
+            return Err(SummonError::NotMatched);
+        }
+
+        if !sign.is_available(now) {
+            return Err(SummonError::Busy);
+        }
+
+        let timed_out = sign.waiting_host();
+        sign.summoned_by = Some((host, now));
+
+        Ok((sign.clone(), timed_out))
+    }
+
+    /// The owner turned down a summon, so the sign is free again. Returns the host that was
+    /// waiting on it.
+    pub fn reject(&self, owner: SteamId, id: u64) -> Option<SteamId> {
+        match self.state.write().signs.get_mut(&id) {
+            Some(sign) if sign.owner == owner => sign.summoned_by.take().map(|(host, _)| host),
+            _ => None,
+        }
+    }
+
+    /// Free every sign held for `host`, for when they leave. Returns the signs that were freed.
+    pub fn release(&self, host: SteamId) -> Vec<SummonSign> {
+        self.state
+            .write()
+            .signs
+            .values_mut()
+            .filter(|sign| sign.waiting_host() == Some(host))
+            .map(|sign| {
+                sign.summoned_by = None;
+                sign.clone()
+            })
+            .collect()
+    }
+
+    /// Whether a host is waiting to hear back from `owner` about one of their signs.
+    pub fn is_being_summoned(&self, owner: SteamId) -> bool {
+        let now = Utc::now();
+        let state = self.state.read();
+
+        state
+            .owners
+            .get(&owner)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.signs.get(id))
+            .any(|sign| !sign.is_available(now))
+    }
+
+    /// Every player with a sign down or waiting on one.
+    pub fn players(&self) -> Vec<SteamId> {
+        self.state
+            .read()
+            .signs
+            .values()
+            .flat_map(|sign| std::iter::once(sign.owner).chain(sign.waiting_host()))
+            .collect()
+    }
+
+    /// The owner accepted a summon, so move them into the host's world. Once they are in it none
+    /// of their signs are needed any more, so they are all removed and returned along with the
+    /// host. If they can't join, the sign is freed and nothing else is touched.
+    pub fn accept(
+        &self,
+        worlds: &WorldStore,
+        max_phantoms: u32,
+        owner: SteamId,
+        id: u64,
+    ) -> Result<(SteamId, Vec<SummonSign>), AcceptError> {
+        let mut state = self.state.write();
+
+        let sign = match state.signs.get_mut(&id) {
+            Some(sign) if sign.owner == owner => sign,
+            _ => return Err(AcceptError::NotPending),
+        };
+        let host = sign.waiting_host().ok_or(AcceptError::NotPending)?;
+
+        let role = PhantomRole::from(sign.sign_type);
+        if let Err(error) = worlds.join(host, owner, role, max_phantoms) {
+            sign.summoned_by = None;
+
+            return Err(AcceptError::CantJoin { host, error });
+        }
+
+        Ok((host, state.remove_all(owner)))
+    }
+}
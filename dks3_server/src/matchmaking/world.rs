@@ -0,0 +1,277 @@
+//! Multiplayer sessions: who is in whose world.
+//!
+//! A world is tracked from when its first phantom joins until its last one leaves. It is built from
+//! the summons and invasions the server hands out and the leave notifications hosts send, so
+//! matchmaking doesn't only have the phantom counts clients report to go on.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use thiserror::Error;
+
+use crate::matchmaking::covenant::Covenant;
+use crate::matchmaking::session::SteamId;
+use crate::matchmaking::sign::SignType;
+
+/// Why a phantom is in the host's world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PhantomRole {
+    /// Summoned from a white sign to help the host.
+    White,
+    /// Summoned from a red sign to fight the host.
+    Red,
+    /// Broke in with a Red Eye Orb.
+    Invader,
+    /// Sent by their covenant to defend or hunt the host.
+    Covenant(Covenant),
+}
+
+impl From<SignType> for PhantomRole {
+    fn from(sign_type: SignType) -> Self {
+        match sign_type {
+            SignType::White => PhantomRole::White,
+            SignType::Red => PhantomRole::Red,
+        }
+    }
+}
+
+/// Why a phantom left the host's world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaveReason {
+    /// The phantom went home, having done what they came for.
+    Returned,
+    /// The phantom was killed.
+    Died,
+    /// The host sent the phantom away.
+    Dismissed,
+    /// A reason the client gave that we don't know.
+    Unknown(u32),
+    /// The phantom lost their connection to the server.
+    Disconnected,
+    /// The host left, taking the world with them.
+    HostLeft,
+    /// The phantom said they were back on their own without the host telling us they had left.
+    Ended,
+}
+
+impl From<u32> for LeaveReason {
+    /// Reasons as they are numbered in leave notifications. These are our own numbering until
+    /// the client's message table has been mapped.
+    fn from(value: u32) -> Self {
+        match value {
+            0 => LeaveReason::Returned,
+            1 => LeaveReason::Died,
+            2 => LeaveReason::Dismissed,
+            _ => LeaveReason::Unknown(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Phantom {
+    pub steam_id: SteamId,
+    pub role: PhantomRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// The phantoms in a host's world.
+#[derive(Debug, Default)]
+struct World {
+    phantoms: Vec<Phantom>,
+}
+
+/// A phantom leaving a world.
+#[derive(Clone, Debug)]
+pub struct Departure {
+    pub host: SteamId,
+    pub phantom: Phantom,
+    pub reason: LeaveReason,
+    pub left_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum JoinError {
+    #[error("players can't join their own world")]
+    OwnWorld,
+
+    #[error("host's world is full")]
+    WorldFull,
+
+    #[error("host is a phantom in someone else's world")]
+    HostIsPhantom,
+
+    #[error("player is already a phantom in someone's world")]
+    AlreadyPhantom,
+
+    #[error("player has phantoms in their own world")]
+    Hosting,
+}
+
+#[derive(Default, Debug)]
+struct WorldState {
+    /// Worlds with at least one phantom, by host.
+    worlds: HashMap<SteamId, World>,
+    /// The host of the world each phantom is in.
+    hosts: HashMap<SteamId, SteamId>,
+}
+
+impl WorldState {
+    fn remove_phantom(
+        &mut self,
+        host: SteamId,
+        phantom: SteamId,
+        reason: LeaveReason,
+        now: DateTime<Utc>,
+    ) -> Option<Departure> {
+        if self.hosts.get(&phantom) != Some(&host) {
+            return None;
+        }
+
+        self.hosts.remove(&phantom);
+
+        let world = self.worlds.get_mut(&host)?;
+        let index = world
+            .phantoms
+            .iter()
+            .position(|other| other.steam_id == phantom)?;
+        let phantom = world.phantoms.remove(index);
+
+        if world.phantoms.is_empty() {
+            self.worlds.remove(&host);
+        }
+
+        Some(Departure {
+            host,
+            phantom,
+            reason,
+            left_at: now,
+        })
+    }
+}
+
+/// Every world with phantoms in it.
+#[derive(Clone, Debug, Default)]
+pub struct WorldStore {
+    state: Arc<RwLock<WorldState>>,
+}
+
+impl WorldStore {
+    /// Put `phantom` in `host`'s world. Hosts can't take more than `max_phantoms`, phantoms can
+    /// only be in one world at a time, and no one can be a phantom and a host at once.
+    pub fn join(
+        &self,
+        host: SteamId,
+        phantom: SteamId,
+        role: PhantomRole,
+        max_phantoms: u32,
+    ) -> Result<(), JoinError> {
+        if host == phantom {
+            return Err(JoinError::OwnWorld);
+        }
+
+        let mut state = self.state.write();
+
+        if state.hosts.contains_key(&host) {
+            return Err(JoinError::HostIsPhantom);
+        }
+
+        if state.hosts.contains_key(&phantom) {
+            return Err(JoinError::AlreadyPhantom);
+        }
+
+        if state.worlds.contains_key(&phantom) {
+            return Err(JoinError::Hosting);
+        }
+
+        let phantom_count = state
+            .worlds
+            .get(&host)
+            .map_or(0, |world| world.phantoms.len());
+        if phantom_count >= max_phantoms as usize {
+            return Err(JoinError::WorldFull);
+        }
+
+        state
+            .worlds
+            .entry(host)
+            .or_default()
+            .phantoms
+            .push(Phantom {
+                steam_id: phantom,
+                role,
+                joined_at: Utc::now(),
+            });
+        state.hosts.insert(phantom, host);
+
+        Ok(())
+    }
+
+    /// Take `phantom` out of `host`'s world. Returns None if they weren't in it.
+    pub fn leave(&self, host: SteamId, phantom: SteamId, reason: LeaveReason) -> Option<Departure> {
+        self.state
+            .write()
+            .remove_phantom(host, phantom, reason, Utc::now())
+    }
+
+    /// Take the player out of multiplayer altogether, for when they leave or are known to be on
+    /// their own. They leave the world they are a phantom in for `reason`, and any phantoms in
+    /// their own world leave because the host has.
+    pub fn leave_all(&self, steam_id: SteamId, reason: LeaveReason) -> Vec<Departure> {
+        let now = Utc::now();
+        let mut state = self.state.write();
+        let mut departures = vec![];
+
+        if let Some(host) = state.hosts.get(&steam_id).copied() {
+            departures.extend(state.remove_phantom(host, steam_id, reason, now));
+        }
+
+        if let Some(world) = state.worlds.remove(&steam_id) {
+            for phantom in world.phantoms {
+                state.hosts.remove(&phantom.steam_id);
+                departures.push(Departure {
+                    host: steam_id,
+                    phantom,
+                    reason: LeaveReason::HostLeft,
+                    left_at: now,
+                });
+            }
+        }
+
+        departures
+    }
+
+    /// Whether `host` can take another phantom: they aren't a phantom themselves and their world
+    /// has fewer than `max_phantoms` in it.
+    pub fn has_room(&self, host: SteamId, max_phantoms: u32) -> bool {
+        let state = self.state.read();
+
+        !state.hosts.contains_key(&host)
+            && state
+                .worlds
+                .get(&host)
+                .map_or(0, |world| world.phantoms.len())
+                < max_phantoms as usize
+    }
+
+    /// Every host with phantoms and every phantom.
+    pub fn players(&self) -> Vec<SteamId> {
+        let state = self.state.read();
+
+        state
+            .worlds
+            .keys()
+            .chain(state.hosts.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Whether the player is in a multiplayer session, either as a phantom or a host with
+    /// phantoms.
+    pub fn is_in_world(&self, steam_id: SteamId) -> bool {
+        let state = self.state.read();
+
+        state.hosts.contains_key(&steam_id) || state.worlds.contains_key(&steam_id)
+    }
+}
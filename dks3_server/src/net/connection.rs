@@ -1,35 +1,80 @@
 use futures::{SinkExt, TryStreamExt};
+use thiserror::Error;
 use tokio::io::{split, AsyncRead, AsyncWrite};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::info;
+use tracing::{info, Instrument};
 
 use dks3_proto::frame::{CipherMode, Frame, FrameDecoder, FrameEncoder};
 use std::fmt::Debug;
 use std::time::Duration;
 
+use crate::net::ConnectionMetadata;
+
+#[derive(Debug, Error)]
+#[error("connection was closed")]
+pub struct ConnectionClosed;
+
+/// The ciphers used for each direction of a [Connection]. The two directions are independent and
+/// may use different algorithms or keys.
+#[derive(Clone, Debug)]
+pub struct CipherPair {
+    pub inbound: CipherMode,
+    pub outbound: CipherMode,
+}
+
+impl CipherPair {
+    pub fn new(inbound: CipherMode, outbound: CipherMode) -> Self {
+        Self { inbound, outbound }
+    }
+
+    pub fn symmetric(cipher_mode: CipherMode) -> Self {
+        Self {
+            inbound: cipher_mode.clone(),
+            outbound: cipher_mode,
+        }
+    }
+}
+
+/// Work for the connection's IO task. Frames and cipher changes share a single queue so that a
+/// cipher change always lands between the frames written before and after it.
+enum Outbound {
+    Frame(Frame),
+    ChangeCipher {
+        inbound: Option<CipherMode>,
+        outbound: Option<CipherMode>,
+        ack: oneshot::Sender<()>,
+    },
+}
+
 pub struct Connection {
+    metadata: ConnectionMetadata,
     close_tx: broadcast::Sender<()>,
-    cipher_change_tx: mpsc::Sender<CipherMode>,
     inbound_frame_rx: mpsc::Receiver<Frame>,
-    outbound_frame_tx: mpsc::Sender<Frame>,
+    outbound_tx: mpsc::Sender<Outbound>,
     handle: JoinHandle<()>,
 }
 
 impl Connection {
-    pub fn start<Read>(cipher_pair: (CipherMode, CipherMode), stream: Read) -> Connection
+    pub fn start<Read>(
+        cipher_pair: CipherPair,
+        metadata: ConnectionMetadata,
+        stream: Read,
+    ) -> Connection
     where
         Read: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
     {
         let (close_tx, mut close_rx) = broadcast::channel::<()>(1);
-        let (cipher_change_tx, mut cipher_change_rx) = mpsc::channel::<CipherMode>(1);
-        let (outbound_frame_tx, mut outbound_frame_rx) = mpsc::channel::<Frame>(10);
+        let (outbound_tx, mut outbound_rx) = mpsc::channel::<Outbound>(10);
         let (inbound_frame_tx, inbound_frame_rx) = mpsc::channel::<Frame>(10);
-        let (inbound_cipher, outbound_cipher) = cipher_pair;
+        let CipherPair {
+            inbound: inbound_cipher,
+            outbound: outbound_cipher,
+        } = cipher_pair;
 
-        let handle = tokio::spawn(async move {
+        let io_task = async move {
             let (mut stream_reader, mut stream_writer) = split(stream);
             let mut frame_reader =
                 FramedRead::new(&mut stream_reader, FrameDecoder::new(inbound_cipher, false));
@@ -38,20 +83,6 @@ impl Connection {
 
             loop {
                 tokio::select! {
-                    cipher = cipher_change_rx.recv() => {
-                        match cipher {
-                            Some(cipher) => {
-                               info!("Cipher change");
-                                let new_cipher = cipher;
-                                frame_reader.decoder_mut().set_cipher_mode(new_cipher.clone());
-                                frame_writer.encoder_mut().set_cipher_mode(new_cipher.clone());
-                            }
-                            None => {
-                              info!("Cipher channel closed");
-                              break;
-                            }
-                        }
-                    }
                     inbound_frame = frame_reader.next() => {
                         match inbound_frame {
                             Some(Ok(frame)) => {
@@ -67,11 +98,26 @@ impl Connection {
                             }
                         }
                     }
-                    outbound_frame = outbound_frame_rx.recv() => {
-                        match outbound_frame {
-                            Some(frame) => {
+                    outbound = outbound_rx.recv() => {
+                        match outbound {
+                            Some(Outbound::Frame(frame)) => {
                                 let _ = frame_writer.send(frame).await;
                             }
+                            Some(Outbound::ChangeCipher { inbound, outbound, ack }) => {
+                                info!(inbound = ?inbound, outbound = ?outbound, "Cipher change");
+
+                                // The decoder defers this until the end of any partially read
+                                // frame, and every earlier outbound frame has already been sent.
+                                if let Some(cipher) = inbound {
+                                    frame_reader.decoder_mut().set_cipher_mode(cipher);
+                                }
+
+                                if let Some(cipher) = outbound {
+                                    frame_writer.encoder_mut().set_cipher_mode(cipher);
+                                }
+
+                                let _ = ack.send(());
+                            }
                             None => break
                         }
                     }
@@ -81,19 +127,77 @@ impl Connection {
                     }
                 }
             }
-        });
+        };
+        let handle = tokio::spawn(io_task.instrument(tracing::Span::current()));
 
         Connection {
+            metadata,
             close_tx,
             handle,
-            cipher_change_tx,
             inbound_frame_rx,
-            outbound_frame_tx,
+            outbound_tx,
         }
     }
 
-    pub async fn change_cipher_mode(&mut self, cipher_mode: CipherMode) {
-        let _ = self.cipher_change_tx.send(cipher_mode).await;
+    pub fn metadata(&self) -> &ConnectionMetadata {
+        &self.metadata
+    }
+
+    /// Switch both directions to `cipher_mode`, returning once the IO task has applied the change.
+    /// Frames written before this call are sent under the old cipher and inbound frames are decoded
+    /// with the new cipher from the next frame boundary onwards.
+    pub async fn change_cipher_mode(
+        &mut self,
+        cipher_mode: CipherMode,
+    ) -> Result<(), ConnectionClosed> {
+        self.change_cipher_modes(Some(cipher_mode.clone()), Some(cipher_mode))
+            .await
+    }
+
+    /// Switch each direction to its cipher in `cipher_pair`, with the same ordering guarantees as
+    /// [Connection::change_cipher_mode].
+    pub async fn change_cipher_pair(
+        &mut self,
+        cipher_pair: CipherPair,
+    ) -> Result<(), ConnectionClosed> {
+        self.change_cipher_modes(Some(cipher_pair.inbound), Some(cipher_pair.outbound))
+            .await
+    }
+
+    /// Switch only the cipher used to decode inbound frames, from the next frame boundary onwards.
+    pub async fn change_inbound_cipher_mode(
+        &mut self,
+        cipher_mode: CipherMode,
+    ) -> Result<(), ConnectionClosed> {
+        self.change_cipher_modes(Some(cipher_mode), None).await
+    }
+
+    /// Switch only the cipher used to encode outbound frames. Frames written before this call are
+    /// still sent under the old cipher.
+    pub async fn change_outbound_cipher_mode(
+        &mut self,
+        cipher_mode: CipherMode,
+    ) -> Result<(), ConnectionClosed> {
+        self.change_cipher_modes(None, Some(cipher_mode)).await
+    }
+
+    async fn change_cipher_modes(
+        &mut self,
+        inbound: Option<CipherMode>,
+        outbound: Option<CipherMode>,
+    ) -> Result<(), ConnectionClosed> {
+        let (ack, ack_rx) = oneshot::channel();
+
+        self.outbound_tx
+            .send(Outbound::ChangeCipher {
+                inbound,
+                outbound,
+                ack,
+            })
+            .await
+            .map_err(|_| ConnectionClosed)?;
+
+        ack_rx.await.map_err(|_| ConnectionClosed)
     }
 
     pub fn close(&self) {
@@ -105,6 +209,6 @@ impl Connection {
     }
 
     pub async fn write_frame(&self, frame: Frame) {
-        let _ = self.outbound_frame_tx.send(frame).await;
+        let _ = self.outbound_tx.send(Outbound::Frame(frame)).await;
     }
 }
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::net::metrics::ConnectionMetrics;
+
+#[derive(Clone, Debug)]
+pub struct ConnectionLimits {
+    /// Maximum number of concurrent connections accepted by a single listener.
+    pub max_connections: usize,
+
+    /// Maximum number of concurrent connections from a single IP address.
+    pub max_connections_per_ip: usize,
+
+    /// Number of new connections a single IP address may open per second, once its burst is spent.
+    pub accept_rate_per_ip: f64,
+
+    /// Number of new connections a single IP address may open in quick succession.
+    pub accept_burst_per_ip: f64,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum ConnectionRejection {
+    #[error("listener is at its global connection limit")]
+    GlobalLimit,
+
+    #[error("peer is at its concurrent connection limit")]
+    PerIpLimit,
+
+    #[error("peer is opening connections too quickly")]
+    RateLimited,
+}
+
+/// Where the limiter reads the time from when refilling token buckets.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug)]
+struct PeerState {
+    active: usize,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    active: usize,
+    peers: HashMap<IpAddr, PeerState>,
+    next_sweep_at: usize,
+}
+
+/// Admission control for accepted sockets, applied before any expensive work such as the RSA
+/// handshake is done on behalf of a peer.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    metrics: Arc<ConnectionMetrics>,
+    clock: Box<dyn Clock>,
+    state: Mutex<LimiterState>,
+}
+
+impl ConnectionLimiter {
+    const MIN_SWEEP_THRESHOLD: usize = 1024;
+
+    pub fn new(limits: ConnectionLimits, metrics: Arc<ConnectionMetrics>) -> Self {
+        Self::with_clock(limits, metrics, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        limits: ConnectionLimits,
+        metrics: Arc<ConnectionMetrics>,
+        clock: Box<dyn Clock>,
+    ) -> Self {
+        Self {
+            limits,
+            metrics,
+            clock,
+            state: Mutex::new(LimiterState {
+                next_sweep_at: Self::MIN_SWEEP_THRESHOLD,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        ip: IpAddr,
+    ) -> Result<ConnectionPermit, ConnectionRejection> {
+        let result = self.admit(ip);
+
+        match result {
+            Ok(()) => {
+                self.metrics.record_accepted();
+
+                Ok(ConnectionPermit {
+                    limiter: self.clone(),
+                    ip: Some(ip),
+                })
+            }
+            Err(rejection) => {
+                self.metrics.record_rejected(rejection);
+
+                Err(rejection)
+            }
+        }
+    }
+
+    /// Hold a slot against the global limit for a connection whose peer isn't known yet, such as
+    /// one still sending its PROXY header. The permit has to be given the peer's address with
+    /// [ConnectionPermit::assign] before the connection is served.
+    pub fn try_acquire_pending(self: &Arc<Self>) -> Result<ConnectionPermit, ConnectionRejection> {
+        let mut state = self.state.lock();
+
+        if state.active >= self.limits.max_connections {
+            drop(state);
+            self.metrics
+                .record_rejected(ConnectionRejection::GlobalLimit);
+
+            return Err(ConnectionRejection::GlobalLimit);
+        }
+
+        state.active += 1;
+
+        Ok(ConnectionPermit {
+            limiter: self.clone(),
+            ip: None,
+        })
+    }
+
+    fn admit(&self, ip: IpAddr) -> Result<(), ConnectionRejection> {
+        let mut state = self.state.lock();
+
+        if state.active >= self.limits.max_connections {
+            return Err(ConnectionRejection::GlobalLimit);
+        }
+
+        self.admit_peer(&mut state, ip)?;
+        state.active += 1;
+
+        Ok(())
+    }
+
+    /// Check and take a slot against the per-IP limits, leaving the global count alone.
+    fn admit_peer(&self, state: &mut LimiterState, ip: IpAddr) -> Result<(), ConnectionRejection> {
+        let now = self.clock.now();
+
+        if state.peers.len() >= state.next_sweep_at {
+            self.sweep(state, now);
+        }
+
+        let burst = self.limits.accept_burst_per_ip.max(1.0);
+        let peer = state.peers.entry(ip).or_insert(PeerState {
+            active: 0,
+            tokens: burst,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(peer.last_refill).as_secs_f64();
+        peer.tokens = (peer.tokens + elapsed * self.limits.accept_rate_per_ip).min(burst);
+        peer.last_refill = now;
+
+        if peer.active >= self.limits.max_connections_per_ip {
+            return Err(ConnectionRejection::PerIpLimit);
+        }
+
+        if peer.tokens < 1.0 {
+            return Err(ConnectionRejection::RateLimited);
+        }
+
+        peer.tokens -= 1.0;
+        peer.active += 1;
+
+        Ok(())
+    }
+
+    /// Forget peers that have no open connections and whose token bucket has fully refilled, as
+    /// they are indistinguishable from a peer we have never seen.
+    fn sweep(&self, state: &mut LimiterState, now: Instant) {
+        let burst = self.limits.accept_burst_per_ip.max(1.0);
+        let rate = self.limits.accept_rate_per_ip;
+
+        state.peers.retain(|_, peer| {
+            let elapsed = now.duration_since(peer.last_refill).as_secs_f64();
+
+            peer.active > 0 || peer.tokens + elapsed * rate < burst
+        });
+
+        state.next_sweep_at = (state.peers.len() * 2).max(Self::MIN_SWEEP_THRESHOLD);
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut state = self.state.lock();
+
+        state.active = state.active.saturating_sub(1);
+
+        if let Some(peer) = ip.and_then(|ip| state.peers.get_mut(&ip)) {
+            peer.active = peer.active.saturating_sub(1);
+        }
+    }
+}
+
+/// A slot held against the [ConnectionLimiter] for as long as a connection is open.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    /// The peer the slot is counted against, or None while it is only held against the global
+    /// limit.
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionPermit {
+    /// Count a pending permit against the peer it turned out to be for. If the peer is over its
+    /// limits the permit is released and the rejection returned.
+    pub fn assign(mut self, ip: IpAddr) -> Result<Self, ConnectionRejection> {
+        let limiter = self.limiter.clone();
+        let result = limiter.admit_peer(&mut limiter.state.lock(), ip);
+
+        match result {
+            Ok(()) => {
+                limiter.metrics.record_accepted();
+                self.ip = Some(ip);
+
+                Ok(self)
+            }
+            Err(rejection) => {
+                limiter.metrics.record_rejected(rejection);
+
+                Err(rejection)
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    fn limiter(
+        max_connections: usize,
+        max_connections_per_ip: usize,
+        accept_rate_per_ip: f64,
+        accept_burst_per_ip: f64,
+    ) -> (Arc<ConnectionLimiter>, ManualClock) {
+        let limits = ConnectionLimits {
+            max_connections,
+            max_connections_per_ip,
+            accept_rate_per_ip,
+            accept_burst_per_ip,
+        };
+        let clock = ManualClock(Arc::new(Mutex::new(Instant::now())));
+        let limiter = ConnectionLimiter::with_clock(
+            limits,
+            Arc::new(ConnectionMetrics::default()),
+            Box::new(clock.clone()),
+        );
+
+        (Arc::new(limiter), clock)
+    }
+
+    fn ip(n: u32) -> IpAddr {
+        Ipv4Addr::from(0x0A00_0000 + n).into()
+    }
+
+    #[test]
+    fn burst_is_spent_then_refilled_at_the_accept_rate() {
+        let (limiter, clock) = limiter(100, 100, 2.0, 3.0);
+
+        for _ in 0..3 {
+            limiter.try_acquire(ip(1)).unwrap();
+        }
+        assert_eq!(
+            limiter.try_acquire(ip(1)).unwrap_err(),
+            ConnectionRejection::RateLimited
+        );
+
+        // Half a second at two per second refills one token.
+        clock.advance(Duration::from_millis(500));
+        limiter.try_acquire(ip(1)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(1)).unwrap_err(),
+            ConnectionRejection::RateLimited
+        );
+
+        // The bucket never holds more than the burst, however long the peer waits.
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            limiter.try_acquire(ip(1)).unwrap();
+        }
+        assert_eq!(
+            limiter.try_acquire(ip(1)).unwrap_err(),
+            ConnectionRejection::RateLimited
+        );
+
+        // Other peers have their own bucket.
+        limiter.try_acquire(ip(2)).unwrap();
+
+        let metrics = limiter.metrics.snapshot();
+        assert_eq!(metrics.accepted, 8);
+        assert_eq!(metrics.rejected_rate_limited, 3);
+    }
+
+    #[test]
+    fn per_ip_limit_counts_open_connections() {
+        let (limiter, _) = limiter(100, 2, 0.0, 100.0);
+
+        let first = limiter.try_acquire(ip(1)).unwrap();
+        let _second = limiter.try_acquire(ip(1)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(1)).unwrap_err(),
+            ConnectionRejection::PerIpLimit
+        );
+        limiter.try_acquire(ip(2)).unwrap();
+
+        drop(first);
+        limiter.try_acquire(ip(1)).unwrap();
+
+        assert_eq!(limiter.metrics.snapshot().rejected_per_ip_limit, 1);
+    }
+
+    #[test]
+    fn global_limit_counts_every_peer() {
+        let (limiter, _) = limiter(2, 100, 0.0, 100.0);
+
+        let first = limiter.try_acquire(ip(1)).unwrap();
+        let _second = limiter.try_acquire(ip(2)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(3)).unwrap_err(),
+            ConnectionRejection::GlobalLimit
+        );
+        assert_eq!(
+            limiter.try_acquire_pending().unwrap_err(),
+            ConnectionRejection::GlobalLimit
+        );
+
+        drop(first);
+        limiter.try_acquire(ip(3)).unwrap();
+
+        assert_eq!(limiter.metrics.snapshot().rejected_global_limit, 2);
+    }
+
+    #[test]
+    fn pending_permit_holds_a_global_slot_until_assigned() {
+        let (limiter, _) = limiter(2, 1, 0.0, 100.0);
+
+        let _held = limiter.try_acquire(ip(1)).unwrap();
+        let pending = limiter.try_acquire_pending().unwrap();
+        assert_eq!(limiter.state.lock().active, 2);
+        assert_eq!(
+            limiter.try_acquire(ip(2)).unwrap_err(),
+            ConnectionRejection::GlobalLimit
+        );
+
+        // Assigned to a peer that is already at its limit, the permit is given back.
+        assert_eq!(
+            pending.assign(ip(1)).unwrap_err(),
+            ConnectionRejection::PerIpLimit
+        );
+        assert_eq!(limiter.state.lock().active, 1);
+
+        let permit = limiter
+            .try_acquire_pending()
+            .unwrap()
+            .assign(ip(2))
+            .unwrap();
+        assert_eq!(limiter.state.lock().peers[&ip(2)].active, 1);
+
+        drop(permit);
+        let state = limiter.state.lock();
+        assert_eq!(state.active, 1);
+        assert_eq!(state.peers[&ip(2)].active, 0);
+    }
+
+    #[test]
+    fn sweep_forgets_idle_peers_once_refilled() {
+        let (limiter, clock) = limiter(usize::MAX, 100, 1.0, 2.0);
+        let threshold = ConnectionLimiter::MIN_SWEEP_THRESHOLD as u32;
+
+        let _open = limiter.try_acquire(ip(0)).unwrap();
+        for n in 1..threshold {
+            limiter.try_acquire(ip(n)).unwrap();
+        }
+
+        // Every peer has spent a token, so none can be forgotten yet.
+        limiter.try_acquire(ip(threshold)).unwrap();
+        {
+            let state = limiter.state.lock();
+            assert_eq!(state.peers.len(), threshold as usize + 1);
+            assert_eq!(state.next_sweep_at, 2 * threshold as usize);
+        }
+
+        for n in threshold + 1..2 * threshold {
+            limiter.try_acquire(ip(n)).unwrap();
+        }
+        clock.advance(Duration::from_secs(1));
+        limiter.try_acquire(ip(u32::MAX >> 8)).unwrap();
+
+        // Only the peer with an open connection and the one just admitted are left.
+        let state = limiter.state.lock();
+        assert_eq!(state.peers.len(), 2);
+        assert!(state.peers.contains_key(&ip(0)));
+        assert_eq!(state.next_sweep_at, ConnectionLimiter::MIN_SWEEP_THRESHOLD);
+    }
+}
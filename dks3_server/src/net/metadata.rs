@@ -0,0 +1,52 @@
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A process-wide unique identifier for a single client connection, shared by every
+/// log span emitted on behalf of that connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub fn next() -> Self {
+        ConnectionId(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionMetadata {
+    pub id: ConnectionId,
+    /// The client's address. For listeners using the PROXY protocol this is the address reported by
+    /// the load balancer rather than the socket's peer.
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// The load balancer the connection arrived through, if any.
+    pub proxied_by: Option<SocketAddr>,
+    pub connected_at: DateTime<Utc>,
+}
+
+impl ConnectionMetadata {
+    pub fn new(peer_addr: SocketAddr, local_addr: SocketAddr) -> Self {
+        Self {
+            id: ConnectionId::next(),
+            peer_addr,
+            local_addr,
+            proxied_by: None,
+            connected_at: Utc::now(),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::net::limiter::ConnectionRejection;
+
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    accepted: AtomicU64,
+    rejected_global_limit: AtomicU64,
+    rejected_per_ip_limit: AtomicU64,
+    rejected_rate_limited: AtomicU64,
+    invalid_proxy_header: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionMetricsSnapshot {
+    pub accepted: u64,
+    pub rejected_global_limit: u64,
+    pub rejected_per_ip_limit: u64,
+    pub rejected_rate_limited: u64,
+    pub invalid_proxy_header: u64,
+}
+
+impl ConnectionMetrics {
+    pub fn record_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self, rejection: ConnectionRejection) {
+        let counter = match rejection {
+            ConnectionRejection::GlobalLimit => &self.rejected_global_limit,
+            ConnectionRejection::PerIpLimit => &self.rejected_per_ip_limit,
+            ConnectionRejection::RateLimited => &self.rejected_rate_limited,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_invalid_proxy_header(&self) {
+        self.invalid_proxy_header.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionMetricsSnapshot {
+        ConnectionMetricsSnapshot {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected_global_limit: self.rejected_global_limit.load(Ordering::Relaxed),
+            rejected_per_ip_limit: self.rejected_per_ip_limit.load(Ordering::Relaxed),
+            rejected_rate_limited: self.rejected_rate_limited.load(Ordering::Relaxed),
+            invalid_proxy_header: self.invalid_proxy_header.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ConnectionMetricsSnapshot {
+    pub fn rejected(&self) -> u64 {
+        self.rejected_global_limit + self.rejected_per_ip_limit + self.rejected_rate_limited
+    }
+}
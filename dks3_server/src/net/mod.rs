@@ -1,6 +1,11 @@
-pub use connection::Connection;
+pub use connection::{CipherPair, Connection, ConnectionClosed};
 pub use dks3_proto::frame::CipherMode;
+pub use metadata::{ConnectionId, ConnectionMetadata};
 
 mod connection;
+pub mod limiter;
 pub mod message;
+mod metadata;
+pub mod metrics;
+mod proxy_protocol;
 pub mod server;
@@ -0,0 +1,320 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug, Error)]
+pub enum ProxyProtocolError {
+    #[error("connection did not begin with a PROXY protocol header")]
+    MissingHeader,
+
+    #[error("malformed PROXY protocol v1 header")]
+    InvalidV1Header,
+
+    #[error("malformed PROXY protocol v2 header")]
+    InvalidV2Header,
+
+    #[error("i/o error while reading PROXY protocol header")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+}
+
+/// The addresses a load balancer reported for the client it is proxying. Both are `None` when the
+/// balancer opened the connection on its own behalf, e.g. for a health check.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+/// Read a PROXY protocol v1 or v2 header from the start of `stream`. Exactly the header is
+/// consumed, leaving the client's own data in the stream.
+pub async fn read_header<S>(stream: &mut S) -> Result<ProxyHeader, ProxyProtocolError>
+where
+    S: AsyncRead + Unpin,
+{
+    // The shortest valid header, "PROXY UNKNOWN\r\n", is longer than the v2 signature.
+    let mut prefix = [0u8; 12];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2_header(stream).await
+    } else if prefix.starts_with(V1_PREFIX) {
+        read_v1_header(stream, &prefix).await
+    } else {
+        Err(ProxyProtocolError::MissingHeader)
+    }
+}
+
+async fn read_v1_header<S>(stream: &mut S, prefix: &[u8]) -> Result<ProxyHeader, ProxyProtocolError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::with_capacity(V1_MAX_LENGTH);
+    line.extend_from_slice(prefix);
+
+    // Reading a byte at a time avoids consuming anything past the end of the header.
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::InvalidV1Header);
+        }
+
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+        .map_err(|_| ProxyProtocolError::InvalidV1Header)?;
+
+    parse_v1_addresses(line).ok_or(ProxyProtocolError::InvalidV1Header)
+}
+
+fn parse_v1_addresses(line: &str) -> Option<ProxyHeader> {
+    let mut fields = line.split(' ');
+
+    match fields.next()? {
+        "UNKNOWN" => return Some(ProxyHeader::default()),
+        "TCP4" | "TCP6" => {}
+        _ => return None,
+    }
+
+    let source_ip: IpAddr = fields.next()?.parse().ok()?;
+    let destination_ip: IpAddr = fields.next()?.parse().ok()?;
+    let source_port: u16 = fields.next()?.parse().ok()?;
+    let destination_port: u16 = fields.next()?.parse().ok()?;
+
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some(ProxyHeader {
+        source: Some(SocketAddr::new(source_ip, source_port)),
+        destination: Some(SocketAddr::new(destination_ip, destination_port)),
+    })
+}
+
+async fn read_v2_header<S>(stream: &mut S) -> Result<ProxyHeader, ProxyProtocolError>
+where
+    S: AsyncRead + Unpin,
+{
+    // Checked before the length is trusted, so garbage after the signature isn't read as a body.
+    let version_command = stream.read_u8().await?;
+    let command = version_command & 0x0F;
+    if version_command >> 4 != 2 || command > 0x1 {
+        return Err(ProxyProtocolError::InvalidV2Header);
+    }
+
+    let family_protocol = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).await?;
+
+    // LOCAL: the connection was made by the proxy itself and carries no client address.
+    if command == 0x0 {
+        return Ok(ProxyHeader::default());
+    }
+
+    // Any trailing TLVs have already been consumed as part of the body and are ignored.
+    match family_protocol >> 4 {
+        0x1 if body.len() >= 12 => {
+            let source_ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let destination_ip = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(source_ip.into(), port(&body[8..10]))),
+                destination: Some(SocketAddr::new(destination_ip.into(), port(&body[10..12]))),
+            })
+        }
+        0x2 if body.len() >= 36 => {
+            let mut source_ip = [0u8; 16];
+            let mut destination_ip = [0u8; 16];
+            source_ip.copy_from_slice(&body[0..16]);
+            destination_ip.copy_from_slice(&body[16..32]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(
+                    Ipv6Addr::from(source_ip).into(),
+                    port(&body[32..34]),
+                )),
+                destination: Some(SocketAddr::new(
+                    Ipv6Addr::from(destination_ip).into(),
+                    port(&body[34..36]),
+                )),
+            })
+        }
+        // UNSPEC and AF_UNIX sources have no IP address to report.
+        0x0 | 0x3 => Ok(ProxyHeader::default()),
+        _ => Err(ProxyProtocolError::InvalidV2Header),
+    }
+}
+
+fn port(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(mut input: &[u8]) -> (Result<ProxyHeader, ProxyProtocolError>, &[u8]) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(read_header(&mut input));
+
+        (result, input)
+    }
+
+    fn v2(version_command: u8, family_protocol: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(version_command);
+        header.push(family_protocol);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    fn addr(s: &str) -> Option<SocketAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let (result, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 50031\r\nclient data");
+
+        let header = result.unwrap();
+        assert_eq!(header.source, addr("192.0.2.1:56324"));
+        assert_eq!(header.destination, addr("198.51.100.2:50031"));
+        assert_eq!(rest, b"client data");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let (result, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 50031\r\n");
+
+        let header = result.unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:56324"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:50031"));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let (result, rest) = read(b"PROXY UNKNOWN\r\nclient data");
+
+        assert_eq!(result.unwrap(), ProxyHeader::default());
+        assert_eq!(rest, b"client data");
+    }
+
+    #[test]
+    fn v1_truncated() {
+        let (result, _) = read(b"PROXY TCP4 192.0.2.1 198.51");
+
+        assert!(matches!(result, Err(ProxyProtocolError::Io { .. })));
+    }
+
+    #[test]
+    fn v1_missing_crlf() {
+        let mut line = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 50031\n".to_vec();
+        line.resize(V1_MAX_LENGTH + 16, b' ');
+        let (result, _) = read(&line);
+
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV1Header)));
+    }
+
+    #[test]
+    fn v1_malformed_addresses() {
+        let (result, _) = read(b"PROXY TCP4 192.0.2.1 not-an-ip 56324 50031\r\n");
+
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV1Header)));
+    }
+
+    #[test]
+    fn v2_proxy_tcp4() {
+        let body = [192, 0, 2, 1, 198, 51, 100, 2, 0xDC, 0x04, 0xC3, 0x6F];
+        let mut input = v2(0x21, 0x11, &body);
+        input.extend_from_slice(b"client data");
+        let (result, rest) = read(&input);
+
+        let header = result.unwrap();
+        assert_eq!(header.source, addr("192.0.2.1:56324"));
+        assert_eq!(header.destination, addr("198.51.100.2:50031"));
+        assert_eq!(rest, b"client data");
+    }
+
+    #[test]
+    fn v2_proxy_tcp6_with_tlvs() {
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut body = source.octets().to_vec();
+        body.extend_from_slice(&destination.octets());
+        body.extend_from_slice(&[0xDC, 0x04, 0xC3, 0x6F]);
+        // A NOOP TLV, which should be skipped.
+        body.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        let input = v2(0x21, 0x21, &body);
+        let (result, rest) = read(&input);
+
+        let header = result.unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:56324"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:50031"));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn v2_local() {
+        let mut input = v2(0x20, 0x00, &[0xAA; 4]);
+        input.extend_from_slice(b"client data");
+        let (result, rest) = read(&input);
+
+        assert_eq!(result.unwrap(), ProxyHeader::default());
+        assert_eq!(rest, b"client data");
+    }
+
+    #[test]
+    fn v2_bad_version() {
+        let mut input = v2(0x11, 0x11, &[]);
+        // A length that would swallow the rest of the stream if it were trusted.
+        input[14..16].copy_from_slice(&[0xFF, 0xFF]);
+        input.extend_from_slice(b"client data");
+        let (result, rest) = read(&input);
+
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV2Header)));
+        assert_eq!(rest, &input[13..]);
+    }
+
+    #[test]
+    fn v2_bad_command() {
+        let (result, _) = read(&v2(0x22, 0x11, &[0; 12]));
+
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV2Header)));
+    }
+
+    #[test]
+    fn v2_length_beyond_buffer() {
+        let mut input = v2(0x21, 0x11, &[0; 12]);
+        input[14..16].copy_from_slice(&64u16.to_be_bytes());
+        let (result, _) = read(&input);
+
+        assert!(matches!(result, Err(ProxyProtocolError::Io { .. })));
+    }
+
+    #[test]
+    fn v2_short_address_block() {
+        let (result, _) = read(&v2(0x21, 0x11, &[0; 8]));
+
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV2Header)));
+    }
+
+    #[test]
+    fn not_proxy_protocol() {
+        let (result, _) = read(b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03\x00client hello");
+
+        assert!(matches!(result, Err(ProxyProtocolError::MissingHeader)));
+    }
+}
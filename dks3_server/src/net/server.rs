@@ -1,13 +1,21 @@
 use std::marker::PhantomData;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use tokio::io;
-use tokio::net::TcpListener;
-use tracing::{info, info_span};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{field, info, info_span, Instrument};
 
-use crate::net::{CipherMode, Connection};
+use crate::net::limiter::{
+    ConnectionLimiter, ConnectionLimits, ConnectionPermit, ConnectionRejection,
+};
+use crate::net::metrics::ConnectionMetrics;
+use crate::net::proxy_protocol;
+use crate::net::{CipherPair, Connection, ConnectionMetadata};
+
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[async_trait]
 pub trait ConnectionHandler<Ctx>: Default + Send + 'static
@@ -25,7 +33,10 @@ where
     Handler: ConnectionHandler<Ctx>,
 {
     bind_address: Vec<SocketAddr>,
-    cipher_pair: (CipherMode, CipherMode),
+    cipher_pair: CipherPair,
+    limiter: Arc<ConnectionLimiter>,
+    metrics: Arc<ConnectionMetrics>,
+    proxy_protocol: bool,
     context: Ctx,
     _handler: PhantomData<Handler>,
 }
@@ -37,20 +48,32 @@ where
 {
     pub fn new<Addrs>(
         bind_addresses: Addrs,
-        cipher_pair: (CipherMode, CipherMode),
+        cipher_pair: CipherPair,
+        limits: ConnectionLimits,
+        proxy_protocol: bool,
         context: Ctx,
     ) -> Self
     where
         Addrs: ToSocketAddrs,
     {
+        let metrics = Arc::new(ConnectionMetrics::default());
+        let limiter = Arc::new(ConnectionLimiter::new(limits, metrics.clone()));
+
         Self {
             bind_address: bind_addresses.to_socket_addrs().unwrap().collect(),
             cipher_pair,
+            limiter,
+            metrics,
+            proxy_protocol,
             context,
             _handler: PhantomData,
         }
     }
 
+    pub fn metrics(&self) -> &Arc<ConnectionMetrics> {
+        &self.metrics
+    }
+
     pub async fn run(&mut self) -> crate::Result<()> {
         info!("Starting {} server", Handler::description());
 
@@ -65,15 +88,46 @@ where
 
         loop {
             match self.accept(&mut listener).await {
-                Ok(mut connection) => {
+                Ok((stream, metadata)) if self.proxy_protocol => {
+                    // The client's address is only known once the header has been read, so the
+                    // per-IP limits have to wait until then. The global limit applies straight
+                    // away so connections stuck before their header still count towards it.
+                    let pending = match self.limiter.try_acquire_pending() {
+                        Ok(pending) => pending,
+                        Err(rejection) => {
+                            Self::log_rejection(&self.metrics, &metadata, rejection);
+                            continue;
+                        }
+                    };
+
+                    let metrics = self.metrics.clone();
+                    let cipher_pair = self.cipher_pair.clone();
                     let ctx = self.context.clone();
 
                     tokio::spawn(async move {
-                        let mut handler = Handler::default();
+                        let proxied = Self::read_proxy_header(stream, metadata, &metrics).await;
 
-                        handler.run(&mut connection, ctx).await
+                        if let Some((stream, metadata)) = proxied {
+                            match pending.assign(metadata.peer_addr.ip()) {
+                                Ok(permit) => {
+                                    Self::serve(stream, metadata, permit, cipher_pair, ctx).await
+                                }
+                                Err(rejection) => {
+                                    Self::log_rejection(&metrics, &metadata, rejection)
+                                }
+                            }
+                        }
                     });
                 }
+                Ok((stream, metadata)) => {
+                    // Dropping a rejected stream closes the socket before any handshake is read.
+                    if let Some(permit) = Self::admit(&self.limiter, &self.metrics, &metadata) {
+                        let cipher_pair = self.cipher_pair.clone();
+                        let ctx = self.context.clone();
+
+                        tokio::spawn(Self::serve(stream, metadata, permit, cipher_pair, ctx));
+                    }
+                }
                 Err(e) => {
                     info!("Error while accepting connection");
                     break;
@@ -84,13 +138,121 @@ where
         Ok(())
     }
 
-    async fn accept(&mut self, listener: &mut TcpListener) -> crate::Result<Connection> {
+    fn admit(
+        limiter: &Arc<ConnectionLimiter>,
+        metrics: &ConnectionMetrics,
+        metadata: &ConnectionMetadata,
+    ) -> Option<ConnectionPermit> {
+        match limiter.try_acquire(metadata.peer_addr.ip()) {
+            Ok(permit) => Some(permit),
+            Err(rejection) => {
+                Self::log_rejection(metrics, metadata, rejection);
+                None
+            }
+        }
+    }
+
+    fn log_rejection(
+        metrics: &ConnectionMetrics,
+        metadata: &ConnectionMetadata,
+        rejection: ConnectionRejection,
+    ) {
+        info!(
+            peer = %metadata.peer_addr,
+            reason = %rejection,
+            rejected = metrics.snapshot().rejected(),
+            "Rejected {} connection",
+            Handler::description()
+        );
+    }
+
+    async fn read_proxy_header(
+        mut stream: TcpStream,
+        mut metadata: ConnectionMetadata,
+        metrics: &ConnectionMetrics,
+    ) -> Option<(TcpStream, ConnectionMetadata)> {
+        let header = tokio::time::timeout(
+            PROXY_HEADER_TIMEOUT,
+            proxy_protocol::read_header(&mut stream),
+        )
+        .await;
+
+        match header {
+            Ok(Ok(header)) => {
+                if let Some(source) = header.source {
+                    metadata.proxied_by = Some(metadata.peer_addr);
+                    metadata.peer_addr = source;
+                }
+
+                Some((stream, metadata))
+            }
+            Ok(Err(e)) => {
+                metrics.record_invalid_proxy_header();
+                info!(peer = %metadata.peer_addr, error = %e, "Rejected connection with an invalid PROXY header");
+
+                None
+            }
+            Err(_) => {
+                metrics.record_invalid_proxy_header();
+                info!(peer = %metadata.peer_addr, "Timed out waiting for a PROXY header");
+
+                None
+            }
+        }
+    }
+
+    async fn serve(
+        stream: TcpStream,
+        metadata: ConnectionMetadata,
+        permit: ConnectionPermit,
+        cipher_pair: CipherPair,
+        ctx: Ctx,
+    ) {
+        let span = info_span!(
+            "connection",
+            id = %metadata.id,
+            service = Handler::description(),
+            peer = %metadata.peer_addr,
+            steamid = field::Empty,
+        );
+
+        async move {
+            match metadata.proxied_by {
+                Some(proxy) => info!(
+                    "Accepted connection on <{}> via <{}>",
+                    metadata.local_addr, proxy
+                ),
+                None => info!("Accepted connection on <{}>", metadata.local_addr),
+            }
+
+            let mut connection = Connection::start(cipher_pair, metadata, stream);
+            let mut handler = Handler::default();
+
+            handler.run(&mut connection, ctx).await;
+
+            drop(permit);
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn accept(
+        &mut self,
+        listener: &mut TcpListener,
+    ) -> crate::Result<(TcpStream, ConnectionMetadata)> {
         let mut retry_count: u8 = 1;
         let mut backoff: u64 = 500;
 
         loop {
             match listener.accept().await {
-                Ok((stream, _)) => return Ok(Connection::start(self.cipher_pair.clone(), stream)),
+                Ok((stream, peer_addr)) => match stream.local_addr() {
+                    Ok(local_addr) => {
+                        return Ok((stream, ConnectionMetadata::new(peer_addr, local_addr)))
+                    }
+                    Err(e) => {
+                        info!(error = %e, peer = %peer_addr, "Dropping connection without a local address")
+                    }
+                },
                 Err(_) if retry_count < 3 => {
                     tokio::time::sleep(Duration::from_millis(backoff)).await;
 
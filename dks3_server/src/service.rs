@@ -1,2 +1,3 @@
 pub mod auth;
+pub mod game;
 pub mod login;
@@ -12,12 +12,13 @@ use dks3_proto::msg::frpg2_request::{
 use std::time::Duration;
 
 use crate::context::MatchmakingDb;
+use crate::matchmaking::session::{Service, SteamId};
 use crate::net;
 use crate::net::server::{ConnectionHandler, TcpServer};
-use crate::net::Connection;
+use crate::net::{CipherPair, Connection};
 use crate::Config;
 
-use tracing::{error, info};
+use tracing::{error, info, trace};
 
 pub struct AuthConnectionHandler {
     global_counter: u16,
@@ -109,16 +110,35 @@ impl ConnectionHandler<MatchmakingDb> for AuthConnectionHandler {
         let handshake = self.read_message::<RequestHandshake>(conn).await;
         let cwc_key = handshake.aescwckey.as_slice();
 
-        info!("key = {}", hex::encode(cwc_key));
+        trace!(len = cwc_key.len(), "Received auth session key");
 
-        conn.change_cipher_mode(CipherMode::aes128_cwc(cwc_key))
-            .await;
+        // The client answers the init block under CWC, so the switch has to be in place first.
+        if let Err(e) = conn.change_cipher_mode(CipherMode::aes128_cwc(cwc_key)).await {
+            error!(error = %e, "Unable to switch to CWC");
+            return;
+        }
 
         let init_block = [0u8; 16];
         self.write_data(conn, &init_block).await;
 
         let status_req = self.read_message::<GetServiceStatus>(conn).await;
         info!("steamid {}", status_req.steamid);
+        tracing::Span::current().record("steamid", &status_req.steamid.as_str());
+
+        let steam_id = match status_req.steamid.parse::<SteamId>() {
+            Ok(steam_id) => steam_id,
+            Err(e) => {
+                error!(steamid = %status_req.steamid, error = %e, "Client sent an invalid steamid");
+                return;
+            }
+        };
+
+        let _registration = db.sessions().register(
+            steam_id,
+            Service::Auth,
+            conn.metadata().id,
+            Some(status_req.versionnum),
+        );
 
         let status_response = GetServiceStatusResponse {
             id: 2,
@@ -138,9 +158,18 @@ impl ConnectionHandler<MatchmakingDb> for AuthConnectionHandler {
         let server_8bytes = rand::thread_rng().gen::<[u8; 8]>();
         unknown_16bytes.put(&server_8bytes[..]);
 
-        info!("unknown16bytes = {}", hex::encode(&unknown_16bytes));
+        trace!(len = unknown_16bytes.len(), "Exchanged session key bytes");
         self.write_data(conn, &unknown_16bytes[..]).await;
 
+        // We key the CWC cipher on the player's game connection with the exchanged bytes
+        if unknown_16bytes.len() != 16 {
+            error!(len = client_8bytes.len(), "Client sent the wrong number of key bytes");
+            return;
+        }
+
+        let mut session_key = [0u8; 16];
+        session_key.copy_from_slice(&unknown_16bytes[..]);
+
         // Here the client sends us their steam session ticket
         // We could try and validate it but the only struct I could find
         // is 8+ years old and is only good for a rough guide
@@ -155,6 +184,7 @@ impl ConnectionHandler<MatchmakingDb> for AuthConnectionHandler {
 
         let config = db.config();
         let login_token = u64::from_le_bytes(rand::thread_rng().gen::<[u8; 8]>());
+        db.sessions().issue_game_ticket(steam_id, login_token, session_key);
         let mut game_server_info = BytesMut::with_capacity(184);
         generate_game_server_info( &config, login_token, &mut game_server_info);
 
@@ -169,7 +199,13 @@ pub fn create_auth_service(db: &MatchmakingDb) -> TcpServer<MatchmakingDb, AuthC
     let bind_addr = format!("{}:{}", config.server_ip, config.auth_port);
     let inbound_cipher_mode = CipherMode::rsa_pkcs1_oeap(config.rsa_private_key.as_bytes());
     let outbound_cipher_mode = CipherMode::rsa_x931(config.rsa_private_key.as_bytes());
-    let ciphers = (inbound_cipher_mode, outbound_cipher_mode);
-
-    TcpServer::new(bind_addr, ciphers, db.clone())
+    let ciphers = CipherPair::new(inbound_cipher_mode, outbound_cipher_mode);
+
+    TcpServer::new(
+        bind_addr,
+        ciphers,
+        config.connection_limits(),
+        config.auth_proxy_protocol,
+        db.clone(),
+    )
 }
@@ -0,0 +1,463 @@
+//! The game service, which handles matchmaking once a player is logged in.
+//!
+//! This isn't compatible with the real client yet: the framing, transport and message numbers are
+//! our own until the client's have been mapped. It only runs when `experimental_game_service` is
+//! set.
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use prost::Message;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, field, info, warn};
+
+use dks3_proto::frame::{CipherMode, Frame};
+use dks3_proto::msg::frpg2_request::MatchingParameter;
+use dks3_proto::msg::{MessageHeader, MessageType};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::rules::MatchingParameters;
+use crate::matchmaking::session::{Push, SessionEvent, SteamId};
+use crate::matchmaking::world::LeaveReason;
+use crate::net::server::{ConnectionHandler, TcpServer};
+use crate::net::{CipherPair, Connection};
+
+mod announcement;
+mod blood_message;
+mod bloodstain;
+mod boss_defender;
+mod character;
+mod covenant;
+mod ghost;
+mod invasion;
+mod player_status;
+mod quick_match;
+mod ranking;
+mod sign;
+mod world;
+
+/// The player on the other end of a game connection.
+pub struct Player {
+    pub steam_id: SteamId,
+}
+
+pub struct GameConnectionHandler {
+    global_counter: u16,
+    counter: u32,
+}
+
+impl Default for GameConnectionHandler {
+    fn default() -> Self {
+        Self {
+            global_counter: 0,
+            counter: 0,
+        }
+    }
+}
+
+impl From<MatchingParameter> for MatchingParameters {
+    fn from(matching: MatchingParameter) -> Self {
+        Self {
+            soul_level: matching.soul_level,
+            weapon_level: matching.weapon_level,
+            password: matching.password.filter(|password| !password.is_empty()),
+        }
+    }
+}
+
+/// The player's matching parameters, with the password from their status if the request didn't
+/// carry one.
+pub(crate) fn matching_parameters(
+    db: &MatchmakingDb,
+    player: &Player,
+    matching: MatchingParameter,
+) -> MatchingParameters {
+    let mut matching = MatchingParameters::from(matching);
+
+    if matching.password.is_none() {
+        matching.password = db
+            .sessions()
+            .get(player.steam_id)
+            .and_then(|session| session.status.matching.password);
+    }
+
+    matching
+}
+
+/// Parse a steamid sent by the client, logging it if it's invalid.
+pub(crate) fn parse_steam_id(steamid: &str) -> Option<SteamId> {
+    match steamid.parse() {
+        Ok(steam_id) => Some(steam_id),
+        Err(e) => {
+            warn!(steamid, error = %e, "Client sent an invalid steamid");
+            None
+        }
+    }
+}
+
+/// Decode a request, pass it to `handler` and encode the response it returns.
+fn handle<Req, Resp, F>(data: BytesMut, handler: F) -> Result<BytesMut, prost::DecodeError>
+where
+    Req: Message + Default,
+    Resp: Message,
+    F: FnOnce(Req) -> Resp,
+{
+    let response = handler(Req::decode(data)?);
+    let mut response_data = BytesMut::with_capacity(response.encoded_len());
+
+    response
+        .encode(&mut response_data)
+        .expect("Unable to write data");
+
+    Ok(response_data)
+}
+
+impl GameConnectionHandler {
+    async fn write_message(&mut self, conn: &mut Connection, header: MessageHeader, body: &[u8]) {
+        let mut data = BytesMut::with_capacity(MessageHeader::SIZE + body.len());
+        header.encode(&mut data);
+        data.extend_from_slice(body);
+
+        conn.write_frame(Frame::new(self.global_counter, self.counter, data))
+            .await;
+    }
+
+    async fn serve(
+        &mut self,
+        conn: &mut Connection,
+        db: &MatchmakingDb,
+        player: &Player,
+        push_rx: &mut mpsc::UnboundedReceiver<Push>,
+    ) {
+        loop {
+            tokio::select! {
+                frame = conn.read_frame() => {
+                    match frame {
+                        Some(frame) => self.handle_frame(conn, db, player, frame).await,
+                        None => break,
+                    }
+                }
+                Some(push) = push_rx.recv() => {
+                    let header = MessageHeader::new(push.msg_type, 0);
+                    self.write_message(conn, header, &push.data).await;
+                }
+            }
+        }
+    }
+
+    async fn handle_frame(
+        &mut self,
+        conn: &mut Connection,
+        db: &MatchmakingDb,
+        player: &Player,
+        frame: Frame,
+    ) {
+        self.global_counter = frame.global_counter;
+        self.counter = frame.counter;
+
+        let mut data = frame.data;
+        let header = match MessageHeader::decode(&mut data) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!(error = %e, "Discarding malformed message");
+                return;
+            }
+        };
+
+        db.sessions().touch(player.steam_id);
+
+        let msg_type = match header.message_type() {
+            Ok(msg_type) => msg_type,
+            Err(msg_type) => {
+                warn!(msg_type, "Client sent an unknown message type");
+                return;
+            }
+        };
+
+        let response = match msg_type {
+            MessageType::RequestUpdatePlayerStatus => {
+                handle(data, |req| player_status::handle_update(db, player, req))
+            }
+            MessageType::RequestUpdatePlayerCharacter => {
+                handle(data, |req| character::handle_update(db, player, req))
+            }
+            MessageType::RequestGetAnnounceMessageList => {
+                handle(data, |req| announcement::handle_get_list(db, req))
+            }
+            MessageType::RequestCreateBloodMessage => {
+                handle(data, |req| blood_message::handle_create(db, player, req))
+            }
+            MessageType::RequestRemoveBloodMessage => {
+                handle(data, |req| blood_message::handle_remove(db, player, req))
+            }
+            MessageType::RequestReentryBloodMessage => {
+                handle(data, |req| blood_message::handle_reentry(db, player, req))
+            }
+            MessageType::RequestEvaluateBloodMessage => {
+                handle(data, |req| blood_message::handle_evaluate(db, player, req))
+            }
+            MessageType::RequestGetBloodMessageList => {
+                handle(data, |req| blood_message::handle_get_list(db, player, req))
+            }
+            MessageType::RequestCreateBloodstain => {
+                handle(data, |req| bloodstain::handle_create(db, player, req))
+            }
+            MessageType::RequestGetBloodstainList => {
+                handle(data, |req| bloodstain::handle_get_list(db, player, req))
+            }
+            MessageType::RequestGetDeadingGhost => {
+                handle(data, |req| bloodstain::handle_get_deading_ghost(db, req))
+            }
+            MessageType::RequestCreateGhostData => {
+                handle(data, |req| ghost::handle_create(db, player, req))
+            }
+            MessageType::RequestGetGhostDataList => {
+                handle(data, |req| ghost::handle_get_list(db, player, req))
+            }
+            MessageType::RequestCreateSign => {
+                handle(data, |req| sign::handle_create(db, player, req))
+            }
+            MessageType::RequestGetSignList => {
+                handle(data, |req| sign::handle_get_list(db, player, req))
+            }
+            MessageType::RequestSummonSign => {
+                handle(data, |req| sign::handle_summon(db, player, req))
+            }
+            MessageType::RequestAcceptSign => {
+                handle(data, |req| sign::handle_accept(db, player, req))
+            }
+            MessageType::RequestRejectSign => {
+                handle(data, |req| sign::handle_reject(db, player, req))
+            }
+            MessageType::RequestUpdateSign => {
+                handle(data, |req| sign::handle_update(db, player, req))
+            }
+            MessageType::RequestRemoveSign => {
+                handle(data, |req| sign::handle_remove(db, player, req))
+            }
+            MessageType::RequestGetBreakInTargetList => handle(data, |req| {
+                invasion::handle_get_target_list(db, player, req)
+            }),
+            MessageType::RequestBreakInTarget => {
+                handle(data, |req| invasion::handle_break_in(db, player, req))
+            }
+            MessageType::RequestAcceptBreakIn => {
+                handle(data, |req| invasion::handle_accept(db, player, req))
+            }
+            MessageType::RequestRejectBreakIn => {
+                handle(data, |req| invasion::handle_reject(db, player, req))
+            }
+            MessageType::RequestNotifyLeaveGuestPlayer => {
+                handle(data, |req| invasion::handle_leave_guest(db, player, req))
+            }
+            MessageType::RequestRegisterBossDefender => {
+                handle(data, |req| boss_defender::handle_register(db, player, req))
+            }
+            MessageType::RequestUnregisterBossDefender => handle(data, |req| {
+                boss_defender::handle_unregister(db, player, req)
+            }),
+            MessageType::RequestSummonBossDefender => {
+                handle(data, |req| boss_defender::handle_summon(db, player, req))
+            }
+            MessageType::RequestAcceptBossDefender => {
+                handle(data, |req| boss_defender::handle_accept(db, player, req))
+            }
+            MessageType::RequestRejectBossDefender => {
+                handle(data, |req| boss_defender::handle_reject(db, player, req))
+            }
+            MessageType::RequestAcceptCovenantSummon => {
+                handle(data, |req| covenant::handle_accept(db, player, req))
+            }
+            MessageType::RequestRejectCovenantSummon => {
+                handle(data, |req| covenant::handle_reject(db, player, req))
+            }
+            MessageType::RequestRegisterQuickMatch => {
+                handle(data, |req| quick_match::handle_register(db, player, req))
+            }
+            MessageType::RequestSearchQuickMatch => {
+                handle(data, |req| quick_match::handle_search(db, player, req))
+            }
+            MessageType::RequestUnregisterQuickMatch => {
+                handle(data, |req| quick_match::handle_unregister(db, player, req))
+            }
+            MessageType::RequestSendQuickMatchResult => {
+                handle(data, |req| quick_match::handle_send_result(db, player, req))
+            }
+            MessageType::RequestGetQuickMatchRanking => {
+                handle(data, |req| quick_match::handle_get_ranking(db, req))
+            }
+            MessageType::RequestRegisterRankingData => {
+                handle(data, |req| ranking::handle_register(db, player, req))
+            }
+            MessageType::RequestGetRankingData => {
+                handle(data, |req| ranking::handle_get(db, player, req))
+            }
+            MessageType::RequestCountRankingData => {
+                handle(data, |req| ranking::handle_count(db, req))
+            }
+            MessageType::RequestGetCurrentRank => handle(data, |req| {
+                ranking::handle_get_current_rank(db, player, req)
+            }),
+            _ => {
+                warn!(msg_type = ?msg_type, "Client sent a message the game service doesn't handle");
+                return;
+            }
+        };
+
+        match response {
+            Ok(body) => {
+                let header = MessageHeader::new(MessageType::Reply, header.msg_index);
+                self.write_message(conn, header, &body).await;
+            }
+            Err(e) => warn!(msg_type = ?msg_type, error = %e, "Unable to decode request"),
+        }
+    }
+}
+
+#[async_trait]
+impl ConnectionHandler<MatchmakingDb> for GameConnectionHandler {
+    fn description() -> &'static str {
+        "game"
+    }
+
+    async fn run(&mut self, conn: &mut Connection, db: MatchmakingDb) {
+        // The client opens with the login token it was given by the auth service
+        let mut token_frame = match conn.read_frame().await {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        self.global_counter = token_frame.global_counter;
+        self.counter = token_frame.counter;
+
+        if token_frame.data.len() != 8 {
+            error!(
+                len = token_frame.data.len(),
+                "Client sent a malformed login token"
+            );
+            return;
+        }
+
+        let login_token = token_frame.data.get_u64();
+        let ticket = match db.sessions().claim_game_ticket(login_token) {
+            Some(ticket) => ticket,
+            None => {
+                warn!("Client sent an unknown or expired login token");
+                return;
+            }
+        };
+
+        tracing::Span::current().record("steamid", &field::display(ticket.steam_id));
+
+        if let Err(e) = conn
+            .change_cipher_mode(CipherMode::aes128_cwc(&ticket.session_key))
+            .await
+        {
+            error!(error = %e, "Unable to switch to CWC");
+            return;
+        }
+
+        let (push_tx, mut push_rx) = mpsc::unbounded_channel::<Push>();
+        let _registration =
+            db.sessions()
+                .register_game(ticket.steam_id, conn.metadata().id, push_tx);
+        let player = Player {
+            steam_id: ticket.steam_id,
+        };
+
+        info!("Player joined the game service");
+
+        // Sent under the session key so the client knows it can start making requests
+        let init_block = [0u8; 16];
+        conn.write_frame(Frame::new(
+            self.global_counter,
+            self.counter,
+            BytesMut::from(&init_block[..]),
+        ))
+        .await;
+
+        self.serve(conn, &db, &player, &mut push_rx).await;
+
+        info!("Player left the game service");
+    }
+}
+
+/// Take a player who has left out of everything they were part of.
+fn clean_up_after(db: &MatchmakingDb, steam_id: SteamId) {
+    sign::remove_all_signs(db, steam_id);
+    sign::release_signs(db, steam_id);
+    boss_defender::unregister(db, steam_id);
+    db.quick_matches().unregister(steam_id);
+    world::leave_all(db, steam_id, LeaveReason::Disconnected);
+}
+
+/// Tidy up after every player who is still part of something but no longer has a session, for
+/// when disconnect events were missed.
+fn sweep_departed(db: &MatchmakingDb) {
+    let sessions = db.sessions().list();
+    let online: HashSet<SteamId> = sessions.iter().map(|session| session.steam_id).collect();
+    let invaders = sessions
+        .iter()
+        .filter_map(|session| session.invasion.as_ref())
+        .map(|invasion| invasion.invader);
+
+    let departed: HashSet<SteamId> = db
+        .signs()
+        .players()
+        .into_iter()
+        .chain(db.boss_defenders().players())
+        .chain(db.quick_matches().queued())
+        .chain(db.worlds().players())
+        .chain(invaders)
+        .filter(|steam_id| !online.contains(steam_id))
+        .collect();
+
+    for steam_id in departed {
+        info!(steam_id = %steam_id, "Cleaning up after departed player");
+        invasion::end_invasion_by(db, steam_id);
+        clean_up_after(db, steam_id);
+    }
+}
+
+/// Tidy up after players once their last connection closes.
+pub fn spawn_session_cleanup(db: &MatchmakingDb) {
+    let db = db.clone();
+    let mut events = db.sessions().subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(SessionEvent::Disconnected(session)) => {
+                    invasion::end_invasions(&db, &session);
+                    clean_up_after(&db, session.steam_id);
+                }
+                Ok(SessionEvent::Connected(_)) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(
+                        missed,
+                        "Session cleanup fell behind, sweeping for departed players"
+                    );
+                    sweep_departed(&db);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+pub fn create_game_service(db: &MatchmakingDb) -> TcpServer<MatchmakingDb, GameConnectionHandler> {
+    let config = db.config();
+    let bind_addr = format!("{}:{}", config.server_ip, config.game_port);
+    let inbound_cipher_mode = CipherMode::rsa_pkcs1_oeap(config.rsa_private_key.as_bytes());
+    let outbound_cipher_mode = CipherMode::rsa_x931(config.rsa_private_key.as_bytes());
+    let ciphers = CipherPair::new(inbound_cipher_mode, outbound_cipher_mode);
+
+    TcpServer::new(
+        bind_addr,
+        ciphers,
+        config.connection_limits(),
+        config.game_proxy_protocol,
+        db.clone(),
+    )
+}
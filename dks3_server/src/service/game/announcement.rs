@@ -0,0 +1,39 @@
+use chrono::Utc;
+
+use dks3_proto::msg::frpg2_request::{
+    AnnounceMessageData, AnnounceMessageDataList, RequestGetAnnounceMessageList,
+    RequestGetAnnounceMessageListResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::announcement::Announcement;
+
+fn to_proto_list(announcements: &[Announcement]) -> Vec<AnnounceMessageData> {
+    announcements
+        .iter()
+        .enumerate()
+        .map(|(index, announcement)| AnnounceMessageData {
+            // Only the position is known to be used, so the others are left empty
+            unk01: index as u32 + 1,
+            unk02: 0,
+            unk03: 0,
+            header: announcement.header.clone(),
+            message: announcement.message.clone(),
+            date_time: announcement.date.into(),
+        })
+        .collect()
+}
+
+pub fn handle_get_list(
+    db: &MatchmakingDb,
+    _request: RequestGetAnnounceMessageList,
+) -> RequestGetAnnounceMessageListResponse {
+    let announcements = db.announcements().showing(Utc::now());
+
+    RequestGetAnnounceMessageListResponse {
+        items: AnnounceMessageDataList {
+            changes: to_proto_list(&announcements.changes),
+            notices: to_proto_list(&announcements.notices),
+        },
+    }
+}
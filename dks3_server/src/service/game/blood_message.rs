@@ -0,0 +1,203 @@
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    BloodMessageContents as BloodMessageContentsMessage, BloodMessageData,
+    PushRequestEvaluateBloodMessage, RequestCreateBloodMessage, RequestCreateBloodMessageResponse,
+    RequestEvaluateBloodMessage, RequestEvaluateBloodMessageResponse, RequestGetBloodMessageList,
+    RequestGetBloodMessageListResponse, RequestReentryBloodMessage,
+    RequestReentryBloodMessageResponse, RequestRemoveBloodMessage,
+    RequestRemoveBloodMessageResponse,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::blood_message::{
+    BloodMessage, BloodMessageContents, NewBloodMessage, ReenteredBloodMessage,
+};
+use crate::service::game::Player;
+
+impl From<BloodMessageContentsMessage> for BloodMessageContents {
+    fn from(contents: BloodMessageContentsMessage) -> Self {
+        Self {
+            template_id: contents.template_id,
+            word_id: contents.word_id,
+            conjunction_id: contents.conjunction_id,
+            second_template_id: contents.second_template_id,
+            second_word_id: contents.second_word_id,
+            gesture_id: contents.gesture_id,
+        }
+    }
+}
+
+impl From<&BloodMessageContents> for BloodMessageContentsMessage {
+    fn from(contents: &BloodMessageContents) -> Self {
+        Self {
+            template_id: contents.template_id,
+            word_id: contents.word_id,
+            conjunction_id: contents.conjunction_id,
+            second_template_id: contents.second_template_id,
+            second_word_id: contents.second_word_id,
+            gesture_id: contents.gesture_id,
+        }
+    }
+}
+
+impl From<&BloodMessage> for BloodMessageData {
+    fn from(message: &BloodMessage) -> Self {
+        Self {
+            message_id: message.id,
+            player_steamid: message.author.to_string(),
+            character_id: message.character_id,
+            online_area_id: message.online_area_id,
+            cell_id: message.cell_id,
+            contents: (&message.contents).into(),
+            message_data: message.placement.to_vec(),
+            good: message.good,
+            poor: message.poor,
+        }
+    }
+}
+
+pub fn handle_create(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestCreateBloodMessage,
+) -> RequestCreateBloodMessageResponse {
+    let message_id = db.blood_messages().create(NewBloodMessage {
+        author: player.steam_id,
+        character_id: request.character_id,
+        online_area_id: request.online_area_id,
+        cell_id: request.cell_id,
+        contents: request.contents.into(),
+        placement: Bytes::from(request.message_data),
+    });
+
+    match message_id {
+        Some(message_id) => {
+            info!(
+                message_id,
+                online_area_id = request.online_area_id,
+                "Created blood message"
+            );
+
+            RequestCreateBloodMessageResponse { message_id }
+        }
+        None => {
+            info!(
+                online_area_id = request.online_area_id,
+                "Dropped blood message since the limits allow none"
+            );
+
+            RequestCreateBloodMessageResponse { message_id: 0 }
+        }
+    }
+}
+
+pub fn handle_remove(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRemoveBloodMessage,
+) -> RequestRemoveBloodMessageResponse {
+    if db
+        .blood_messages()
+        .remove(player.steam_id, request.message_id)
+    {
+        info!(message_id = request.message_id, "Removed blood message");
+    }
+
+    RequestRemoveBloodMessageResponse {}
+}
+
+pub fn handle_reentry(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestReentryBloodMessage,
+) -> RequestReentryBloodMessageResponse {
+    let messages = request
+        .messages
+        .into_iter()
+        .map(|message| ReenteredBloodMessage {
+            id: Some(message.message_id).filter(|&id| id != 0),
+            message: NewBloodMessage {
+                author: player.steam_id,
+                character_id: message.character_id,
+                online_area_id: message.online_area_id,
+                cell_id: message.cell_id,
+                contents: message.contents.into(),
+                placement: Bytes::from(message.message_data),
+            },
+        })
+        .collect();
+
+    let message_ids = db.blood_messages().reenter(player.steam_id, messages);
+
+    info!(count = message_ids.len(), "Re-entered blood messages");
+
+    RequestReentryBloodMessageResponse { message_ids }
+}
+
+pub fn handle_get_list(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetBloodMessageList,
+) -> RequestGetBloodMessageListResponse {
+    let max_messages = (request.max_messages as usize).min(db.config().blood_message_list_max);
+    let mut rng = rand::thread_rng();
+    let mut messages = Vec::new();
+
+    for area in &request.search_areas {
+        let remaining = max_messages - messages.len();
+        if remaining == 0 {
+            break;
+        }
+
+        let count = (area.max_messages as usize).min(remaining);
+        let selected =
+            db.blood_messages()
+                .list(player.steam_id, area.online_area_id, count, &mut rng);
+
+        messages.extend(selected.iter().map(BloodMessageData::from));
+    }
+
+    RequestGetBloodMessageListResponse { messages }
+}
+
+pub fn handle_evaluate(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestEvaluateBloodMessage,
+) -> RequestEvaluateBloodMessageResponse {
+    let result =
+        db.blood_messages()
+            .evaluate(player.steam_id, request.message_id, request.was_poor);
+
+    let message = match result {
+        Ok(message) => message,
+        Err(e) => {
+            warn!(message_id = request.message_id, error = %e, "Ignoring rating");
+            return RequestEvaluateBloodMessageResponse {};
+        }
+    };
+
+    info!(
+        message_id = message.id,
+        good = message.good,
+        poor = message.poor,
+        "Rated blood message"
+    );
+
+    // The author only hears about it (and regains their HP) if they are online right now
+    let push = PushRequestEvaluateBloodMessage {
+        message_id: message.id,
+        player_steamid: player.steam_id.to_string(),
+        was_poor: request.was_poor,
+    };
+    db.sessions().push(
+        message.author,
+        MessageType::PushRequestEvaluateBloodMessage,
+        &push,
+    );
+
+    RequestEvaluateBloodMessageResponse {}
+}
@@ -0,0 +1,91 @@
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    BloodstainInfo, RequestCreateBloodstain, RequestCreateBloodstainResponse,
+    RequestGetBloodstainList, RequestGetBloodstainListResponse, RequestGetDeadingGhost,
+    RequestGetDeadingGhostResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::bloodstain::{Bloodstain, NewBloodstain};
+use crate::service::game::Player;
+
+impl From<&Bloodstain> for BloodstainInfo {
+    fn from(bloodstain: &Bloodstain) -> Self {
+        Self {
+            bloodstain_id: bloodstain.id,
+            player_steamid: bloodstain.player.to_string(),
+            online_area_id: bloodstain.online_area_id,
+            cell_id: bloodstain.cell_id,
+            data: bloodstain.data.to_vec(),
+        }
+    }
+}
+
+pub fn handle_create(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestCreateBloodstain,
+) -> RequestCreateBloodstainResponse {
+    let result = db.bloodstains().create(NewBloodstain {
+        player: player.steam_id,
+        online_area_id: request.online_area_id,
+        cell_id: request.cell_id,
+        data: Bytes::from(request.data),
+        replay: Bytes::from(request.ghost_data),
+    });
+
+    match result {
+        Ok(bloodstain_id) => info!(
+            bloodstain_id,
+            online_area_id = request.online_area_id,
+            "Created bloodstain"
+        ),
+        Err(e) => warn!(error = %e, "Rejected bloodstain"),
+    }
+
+    RequestCreateBloodstainResponse {}
+}
+
+pub fn handle_get_list(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetBloodstainList,
+) -> RequestGetBloodstainListResponse {
+    let max_bloodstains = (request.max_bloodstains as usize).min(db.config().bloodstain_list_max);
+    let mut rng = rand::thread_rng();
+    let mut bloodstains = Vec::new();
+
+    for area in &request.search_areas {
+        let remaining = max_bloodstains - bloodstains.len();
+        if remaining == 0 {
+            break;
+        }
+
+        let count = (area.max_bloodstains as usize).min(remaining);
+        let selected = db
+            .bloodstains()
+            .list(player.steam_id, area.online_area_id, count, &mut rng);
+
+        bloodstains.extend(selected.iter().map(BloodstainInfo::from));
+    }
+
+    RequestGetBloodstainListResponse { bloodstains }
+}
+
+pub fn handle_get_deading_ghost(
+    db: &MatchmakingDb,
+    request: RequestGetDeadingGhost,
+) -> RequestGetDeadingGhostResponse {
+    let ghost_data = db
+        .bloodstains()
+        .get(request.bloodstain_id)
+        .map(|bloodstain| bloodstain.replay.to_vec())
+        .unwrap_or_default();
+
+    RequestGetDeadingGhostResponse {
+        bloodstain_id: request.bloodstain_id,
+        ghost_data,
+    }
+}
@@ -0,0 +1,154 @@
+use tracing::info;
+
+use dks3_proto::msg::frpg2_request::{
+    PushRequestBossDefenderResult, PushRequestSummonBossDefender, RequestAcceptBossDefender,
+    RequestAcceptBossDefenderResponse, RequestRegisterBossDefender,
+    RequestRegisterBossDefenderResponse, RequestRejectBossDefender,
+    RequestRejectBossDefenderResponse, RequestSummonBossDefender,
+    RequestSummonBossDefenderResponse, RequestUnregisterBossDefender,
+    RequestUnregisterBossDefenderResponse,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::covenant::Covenant;
+use crate::matchmaking::session::SteamId;
+use crate::matchmaking::world::PhantomRole;
+use crate::service::game::{matching_parameters, parse_steam_id, Player};
+
+fn send_result(db: &MatchmakingDb, host: SteamId, defender: SteamId, success: bool) {
+    let push = PushRequestBossDefenderResult {
+        player_steamid: defender.to_string(),
+        success,
+    };
+
+    db.sessions()
+        .push(host, MessageType::PushRequestBossDefenderResult, &push);
+}
+
+/// Stop a player being summoned as a boss, letting any host waiting on them know they aren't
+/// coming.
+pub fn unregister(db: &MatchmakingDb, defender: SteamId) {
+    let summoned_by = db
+        .boss_defenders()
+        .unregister(defender)
+        .and_then(|registration| registration.summoned_by);
+
+    if let Some((host, _)) = summoned_by {
+        send_result(db, host, defender, false);
+    }
+}
+
+pub fn handle_register(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRegisterBossDefender,
+) -> RequestRegisterBossDefenderResponse {
+    let matching = matching_parameters(db, player, request.matching_parameter);
+    db.boss_defenders().register(player.steam_id, matching);
+
+    info!("Registered as a boss defender");
+
+    RequestRegisterBossDefenderResponse {}
+}
+
+pub fn handle_unregister(
+    db: &MatchmakingDb,
+    player: &Player,
+    _request: RequestUnregisterBossDefender,
+) -> RequestUnregisterBossDefenderResponse {
+    unregister(db, player.steam_id);
+
+    RequestUnregisterBossDefenderResponse {}
+}
+
+pub fn handle_summon(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestSummonBossDefender,
+) -> RequestSummonBossDefenderResponse {
+    if !db
+        .worlds()
+        .has_room(player.steam_id, db.config().max_phantoms_per_world)
+    {
+        return RequestSummonBossDefenderResponse { found: false };
+    }
+
+    let matching = matching_parameters(db, player, request.matching_parameter);
+    let defender = db.boss_defenders().summon(
+        db.sessions(),
+        db.worlds(),
+        &db.config().matching_rules,
+        player.steam_id,
+        &matching,
+        &mut rand::thread_rng(),
+    );
+
+    let defender = match defender {
+        Some((defender, timed_out)) => {
+            if let Some(timed_out) = timed_out {
+                info!(defender = %defender, host = %timed_out, "Boss defender summon timed out");
+                send_result(db, timed_out, defender, false);
+            }
+
+            defender
+        }
+        None => return RequestSummonBossDefenderResponse { found: false },
+    };
+
+    let push = PushRequestSummonBossDefender {
+        player_steamid: player.steam_id.to_string(),
+        online_area_id: request.online_area_id,
+    };
+
+    if db
+        .sessions()
+        .push(defender, MessageType::PushRequestSummonBossDefender, &push)
+    {
+        info!(defender = %defender, "Summoning boss defender");
+        RequestSummonBossDefenderResponse { found: true }
+    } else {
+        db.boss_defenders().reject(defender, player.steam_id);
+        RequestSummonBossDefenderResponse { found: false }
+    }
+}
+
+pub fn handle_accept(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestAcceptBossDefender,
+) -> RequestAcceptBossDefenderResponse {
+    if let Some(host) = parse_steam_id(&request.player_steamid) {
+        if db.boss_defenders().accept(player.steam_id, host) {
+            let role = PhantomRole::Covenant(Covenant::SpearsOfTheChurch);
+            let max_phantoms = db.config().max_phantoms_per_world;
+
+            match db.worlds().join(host, player.steam_id, role, max_phantoms) {
+                Ok(()) => {
+                    send_result(db, host, player.steam_id, true);
+                    info!(host = %host, "Boss defender accepted summon");
+                }
+                Err(e) => {
+                    send_result(db, host, player.steam_id, false);
+                    info!(host = %host, error = %e, "Boss defender can't join host");
+                }
+            }
+        }
+    }
+
+    RequestAcceptBossDefenderResponse {}
+}
+
+pub fn handle_reject(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRejectBossDefender,
+) -> RequestRejectBossDefenderResponse {
+    if let Some(host) = parse_steam_id(&request.player_steamid) {
+        if db.boss_defenders().reject(player.steam_id, host) {
+            send_result(db, host, player.steam_id, false);
+        }
+    }
+
+    RequestRejectBossDefenderResponse {}
+}
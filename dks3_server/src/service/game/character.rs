@@ -0,0 +1,67 @@
+use prost::Message;
+use tracing::warn;
+
+use dks3_proto::msg::frpg2_request::{
+    PlayerCharacterData, PlayerCharacterStats, RequestUpdatePlayerCharacter,
+    RequestUpdatePlayerCharacterResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::character::{Character, CharacterStats};
+use crate::service::game::Player;
+
+impl From<PlayerCharacterStats> for CharacterStats {
+    fn from(stats: PlayerCharacterStats) -> Self {
+        Self {
+            vigor: stats.vigor.unwrap_or_default(),
+            attunement: stats.attunement.unwrap_or_default(),
+            endurance: stats.endurance.unwrap_or_default(),
+            vitality: stats.vitality.unwrap_or_default(),
+            strength: stats.strength.unwrap_or_default(),
+            dexterity: stats.dexterity.unwrap_or_default(),
+            intelligence: stats.intelligence.unwrap_or_default(),
+            faith: stats.faith.unwrap_or_default(),
+            luck: stats.luck.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<PlayerCharacterData> for Character {
+    fn from(character: PlayerCharacterData) -> Self {
+        Self {
+            // The client pads the name with trailing NULs
+            name: character
+                .name
+                .map(|name| name.trim_end_matches('\0').to_string()),
+            soul_level: character.soul_level,
+            stats: character.stats.map(CharacterStats::from),
+            equipment: character.equipment,
+        }
+    }
+}
+
+pub fn handle_update(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestUpdatePlayerCharacter,
+) -> RequestUpdatePlayerCharacterResponse {
+    let character_id = request.character_id;
+    let character = match PlayerCharacterData::decode(request.character_data.as_slice()) {
+        Ok(character) => Some(Character::from(character)),
+        Err(e) => {
+            warn!(character_id, error = %e, "Unable to decode character data, keeping it as sent");
+            None
+        }
+    };
+
+    if let Err(e) = db.characters().update(
+        player.steam_id,
+        character_id,
+        character,
+        request.character_data,
+    ) {
+        warn!(character_id, error = %e, "Refused character data");
+    }
+
+    RequestUpdatePlayerCharacterResponse {}
+}
@@ -0,0 +1,140 @@
+use chrono::Utc;
+use tracing::info;
+
+use dks3_proto::msg::frpg2_request::{
+    PushRequestCovenantSummon, RequestAcceptCovenantSummon, RequestAcceptCovenantSummonResponse,
+    RequestRejectCovenantSummon, RequestRejectCovenantSummonResponse,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::covenant::{Covenant, CovenantMatcher};
+use crate::matchmaking::invasion;
+use crate::matchmaking::session::{Session, SteamId};
+use crate::matchmaking::world::PhantomRole;
+use crate::service::game::{parse_steam_id, Player};
+
+fn matcher(db: &MatchmakingDb) -> CovenantMatcher<'_> {
+    let config = db.config();
+
+    CovenantMatcher {
+        sessions: db.sessions(),
+        worlds: db.worlds(),
+        signs: db.signs(),
+        boss_defenders: db.boss_defenders(),
+        rules: &config.matching_rules,
+        settings: &config.covenants,
+        cooldowns: db.covenant_cooldowns(),
+        summons: db.covenant_summons(),
+        max_phantoms: config.max_phantoms_per_world,
+    }
+}
+
+/// Ask `member` to go to `target`'s world. They only join it once they accept.
+fn send_summon(db: &MatchmakingDb, member: SteamId, covenant: Covenant, target: &Session) {
+    db.covenant_summons()
+        .offer(member, target.steam_id, covenant);
+
+    let push = PushRequestCovenantSummon {
+        player_steamid: target.steam_id.to_string(),
+        online_area_id: target.status.online_area_id.unwrap_or_default(),
+        covenant: covenant.into(),
+    };
+
+    if db
+        .sessions()
+        .push(member, MessageType::PushRequestCovenantSummon, &push)
+    {
+        info!(member = %member, target = %target.steam_id, ?covenant, "Summoned covenant member");
+    } else {
+        db.covenant_summons().take(member, target.steam_id);
+    }
+}
+
+/// Send a defender to `host` if they are under a covenant's protection, for when they have just
+/// been invaded.
+pub fn summon_defender(db: &MatchmakingDb, host: SteamId) {
+    let host = match db.sessions().get(host) {
+        Some(host) => host,
+        None => return,
+    };
+
+    if let Some((member, covenant)) = matcher(db).find_defender(&host, &mut rand::thread_rng()) {
+        send_summon(db, member.steam_id, covenant, &host);
+    }
+}
+
+/// Send a guardian after `intruder` if they have just entered an area a covenant guards and their
+/// world is open to invaders.
+pub fn summon_guardian(db: &MatchmakingDb, intruder: SteamId) {
+    let intruder = match db.sessions().get(intruder) {
+        Some(intruder) => intruder,
+        None => return,
+    };
+
+    let max_phantoms = db.config().max_phantoms_per_world;
+    if !invasion::is_invadable(&intruder, db.worlds(), max_phantoms, Utc::now()) {
+        return;
+    }
+
+    if let Some((member, covenant)) = matcher(db).find_guardian(&intruder, &mut rand::thread_rng())
+    {
+        send_summon(db, member.steam_id, covenant, &intruder);
+    }
+}
+
+pub fn handle_accept(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestAcceptCovenantSummon,
+) -> RequestAcceptCovenantSummonResponse {
+    let target = match parse_steam_id(&request.player_steamid) {
+        Some(target) => target,
+        None => return RequestAcceptCovenantSummonResponse { joined: false },
+    };
+
+    let covenant = match db.covenant_summons().take(player.steam_id, target) {
+        Some(covenant) => covenant,
+        None => {
+            info!(target = %target, "Accepted a covenant summon that isn't pending");
+            return RequestAcceptCovenantSummonResponse { joined: false };
+        }
+    };
+
+    let role = PhantomRole::Covenant(covenant);
+    let max_phantoms = db.config().max_phantoms_per_world;
+
+    let joined = match db
+        .worlds()
+        .join(target, player.steam_id, role, max_phantoms)
+    {
+        Ok(()) => {
+            info!(target = %target, ?covenant, "Covenant member accepted summon");
+            true
+        }
+        Err(e) => {
+            info!(target = %target, error = %e, "Covenant member can't join target");
+            false
+        }
+    };
+
+    RequestAcceptCovenantSummonResponse { joined }
+}
+
+pub fn handle_reject(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRejectCovenantSummon,
+) -> RequestRejectCovenantSummonResponse {
+    if let Some(target) = parse_steam_id(&request.player_steamid) {
+        if db
+            .covenant_summons()
+            .take(player.steam_id, target)
+            .is_some()
+        {
+            info!(target = %target, "Covenant member rejected summon");
+        }
+    }
+
+    RequestRejectCovenantSummonResponse {}
+}
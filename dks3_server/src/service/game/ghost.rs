@@ -0,0 +1,72 @@
+use bytes::Bytes;
+use tracing::{debug, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    GhostData, RequestCreateGhostData, RequestCreateGhostDataResponse, RequestGetGhostDataList,
+    RequestGetGhostDataListResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::ghost::Ghost;
+use crate::service::game::Player;
+
+impl From<&Ghost> for GhostData {
+    fn from(ghost: &Ghost) -> Self {
+        Self {
+            ghost_id: ghost.id,
+            player_steamid: ghost.player.to_string(),
+            online_area_id: ghost.online_area_id,
+            ghost_data: ghost.data.to_vec(),
+        }
+    }
+}
+
+pub fn handle_create(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestCreateGhostData,
+) -> RequestCreateGhostDataResponse {
+    let result = db.ghosts().upload(
+        player.steam_id,
+        request.online_area_id,
+        Bytes::from(request.ghost_data),
+    );
+
+    // Clients upload these constantly, so successes are only worth a debug line
+    match result {
+        Ok(ghost_id) => debug!(
+            ghost_id,
+            online_area_id = request.online_area_id,
+            "Stored ghost"
+        ),
+        Err(e) => warn!(error = %e, "Rejected ghost"),
+    }
+
+    RequestCreateGhostDataResponse {}
+}
+
+pub fn handle_get_list(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetGhostDataList,
+) -> RequestGetGhostDataListResponse {
+    let max_ghosts = (request.max_ghosts as usize).min(db.config().ghost_list_max);
+    let mut rng = rand::thread_rng();
+    let mut ghosts = Vec::new();
+
+    for area in &request.search_areas {
+        let remaining = max_ghosts - ghosts.len();
+        if remaining == 0 {
+            break;
+        }
+
+        let count = (area.max_ghosts as usize).min(remaining);
+        let selected = db
+            .ghosts()
+            .list(player.steam_id, area.online_area_id, count, &mut rng);
+
+        ghosts.extend(selected.iter().map(GhostData::from));
+    }
+
+    RequestGetGhostDataListResponse { ghosts }
+}
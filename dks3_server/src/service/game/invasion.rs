@@ -0,0 +1,212 @@
+use std::convert::TryFrom;
+
+use tracing::{info, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    BreakInTargetData, PushRequestBreakInResult, PushRequestBreakInTarget, RequestAcceptBreakIn,
+    RequestAcceptBreakInResponse, RequestBreakInTarget, RequestBreakInTargetResponse,
+    RequestGetBreakInTargetList, RequestGetBreakInTargetListResponse,
+    RequestNotifyLeaveGuestPlayer, RequestNotifyLeaveGuestPlayerResponse, RequestRejectBreakIn,
+    RequestRejectBreakInResponse,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::invasion::{self, BreakInSearch, InvasionType};
+use crate::matchmaking::session::{Session, SteamId};
+use crate::matchmaking::world::{LeaveReason, PhantomRole};
+use crate::service::game::{covenant, matching_parameters, parse_steam_id, world, Player};
+
+fn send_result(db: &MatchmakingDb, invader: SteamId, host: SteamId, success: bool) {
+    let push = PushRequestBreakInResult {
+        player_steamid: host.to_string(),
+        success,
+    };
+
+    db.sessions()
+        .push(invader, MessageType::PushRequestBreakInResult, &push);
+}
+
+/// Free up the worlds a player was part of, for when they leave.
+pub fn end_invasions(db: &MatchmakingDb, session: &Session) {
+    if let Some(invasion) = &session.invasion {
+        if !invasion.accepted {
+            send_result(db, invasion.invader, session.steam_id, false);
+        }
+    }
+
+    end_invasion_by(db, session.steam_id);
+}
+
+/// End the invasion `invader` is on, if any, for when they leave.
+pub fn end_invasion_by(db: &MatchmakingDb, invader: SteamId) {
+    if let Some(host) = invasion::end_all_by(db.sessions(), invader) {
+        info!(host = %host, "Invader left, ending invasion");
+    }
+}
+
+pub fn handle_get_target_list(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetBreakInTargetList,
+) -> RequestGetBreakInTargetListResponse {
+    let invasion_type = match InvasionType::try_from(request.invasion_type) {
+        Ok(invasion_type) => invasion_type,
+        Err(invasion_type) => {
+            warn!(invasion_type, "Client sent an unknown invasion type");
+            return RequestGetBreakInTargetListResponse { targets: vec![] };
+        }
+    };
+
+    let online_area_id = request.online_area_id;
+    let config = db.config();
+    let search = BreakInSearch {
+        invader: player.steam_id,
+        matching: matching_parameters(db, player, request.matching_parameter),
+        online_area_id,
+        invasion_type,
+    };
+    let count = (request.max_targets as usize).min(config.break_in_target_list_max);
+
+    let targets = invasion::find_targets(
+        db.sessions(),
+        db.worlds(),
+        &config.matching_rules,
+        config.max_phantoms_per_world,
+        &search,
+        count,
+        &mut rand::thread_rng(),
+    );
+
+    RequestGetBreakInTargetListResponse {
+        targets: targets
+            .iter()
+            .map(|host| BreakInTargetData {
+                player_steamid: host.steam_id.to_string(),
+                online_area_id,
+            })
+            .collect(),
+    }
+}
+
+pub fn handle_break_in(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestBreakInTarget,
+) -> RequestBreakInTargetResponse {
+    let host = match parse_steam_id(&request.player_steamid) {
+        Some(host) => host,
+        None => return RequestBreakInTargetResponse {},
+    };
+
+    let invasion_type = match InvasionType::try_from(request.invasion_type) {
+        Ok(invasion_type) => invasion_type,
+        Err(invasion_type) => {
+            warn!(invasion_type, "Client sent an unknown invasion type");
+            send_result(db, player.steam_id, host, false);
+            return RequestBreakInTargetResponse {};
+        }
+    };
+
+    let config = db.config();
+    let search = BreakInSearch {
+        invader: player.steam_id,
+        matching: matching_parameters(db, player, request.matching_parameter),
+        online_area_id: request.online_area_id,
+        invasion_type,
+    };
+
+    if let Err(e) = invasion::begin(
+        db.sessions(),
+        db.worlds(),
+        &config.matching_rules,
+        config.max_phantoms_per_world,
+        &search,
+        host,
+    ) {
+        info!(host = %host, error = %e, "Break-in refused");
+        send_result(db, player.steam_id, host, false);
+        return RequestBreakInTargetResponse {};
+    }
+
+    let push = PushRequestBreakInTarget {
+        player_steamid: player.steam_id.to_string(),
+        player_data: request.player_data,
+    };
+
+    if db
+        .sessions()
+        .push(host, MessageType::PushRequestBreakInTarget, &push)
+    {
+        info!(host = %host, "Breaking in");
+    } else {
+        invasion::end(db.sessions(), host, player.steam_id);
+        send_result(db, player.steam_id, host, false);
+    }
+
+    RequestBreakInTargetResponse {}
+}
+
+pub fn handle_accept(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestAcceptBreakIn,
+) -> RequestAcceptBreakInResponse {
+    if let Some(invader) = parse_steam_id(&request.player_steamid) {
+        if invasion::accept(db.sessions(), player.steam_id, invader) {
+            let max_phantoms = db.config().max_phantoms_per_world;
+
+            match db
+                .worlds()
+                .join(player.steam_id, invader, PhantomRole::Invader, max_phantoms)
+            {
+                Ok(()) => {
+                    send_result(db, invader, player.steam_id, true);
+                    info!(invader = %invader, "Invaded");
+
+                    covenant::summon_defender(db, player.steam_id);
+                }
+                Err(e) => {
+                    invasion::end(db.sessions(), player.steam_id, invader);
+                    send_result(db, invader, player.steam_id, false);
+                    info!(invader = %invader, error = %e, "Invader can't join");
+                }
+            }
+        }
+    }
+
+    RequestAcceptBreakInResponse {}
+}
+
+pub fn handle_reject(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRejectBreakIn,
+) -> RequestRejectBreakInResponse {
+    if let Some(invader) = parse_steam_id(&request.player_steamid) {
+        if invasion::end(db.sessions(), player.steam_id, invader) {
+            send_result(db, invader, player.steam_id, false);
+        }
+    }
+
+    RequestRejectBreakInResponse {}
+}
+
+pub fn handle_leave_guest(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestNotifyLeaveGuestPlayer,
+) -> RequestNotifyLeaveGuestPlayerResponse {
+    if let Some(guest) = parse_steam_id(&request.player_steamid) {
+        if invasion::end(db.sessions(), player.steam_id, guest) {
+            info!(invader = %guest, reason = request.reason, "Invasion ended");
+        }
+
+        let reason = LeaveReason::from(request.reason);
+        if let Some(departure) = db.worlds().leave(player.steam_id, guest, reason) {
+            world::log_departure(&departure);
+        }
+    }
+
+    RequestNotifyLeaveGuestPlayerResponse {}
+}
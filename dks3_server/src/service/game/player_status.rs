@@ -0,0 +1,100 @@
+use std::convert::TryFrom;
+
+use tracing::{debug, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    RequestUpdatePlayerStatus, RequestUpdatePlayerStatusResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::covenant::Covenant;
+use crate::matchmaking::player_status::{MultiplayerState, PlayerStatus};
+use crate::matchmaking::world::LeaveReason;
+use crate::service::game::{covenant, world, Player};
+
+/// Apply the fields the client sent to `status`. Returns whether the player entered a new area.
+fn apply_update(
+    status: &mut PlayerStatus,
+    update: dks3_proto::msg::frpg2_request::PlayerStatus,
+) -> bool {
+    let mut entered_area = false;
+
+    if let Some(online_area_id) = update.online_area_id {
+        entered_area = status.online_area_id != Some(online_area_id);
+        status.online_area_id = Some(online_area_id);
+    }
+
+    if let Some(soul_level) = update.soul_level {
+        status.matching.soul_level = soul_level;
+    }
+
+    if let Some(weapon_level) = update.weapon_level {
+        status.matching.weapon_level = weapon_level;
+    }
+
+    if let Some(password) = update.password {
+        status.matching.password = Some(password).filter(|password| !password.is_empty());
+    }
+
+    if let Some(embered) = update.embered {
+        status.embered = embered;
+    }
+
+    if let Some(covenant) = update.covenant {
+        // The client sends 0 when no covenant is equipped
+        status.covenant = match Covenant::try_from(covenant) {
+            Ok(covenant) => Some(covenant),
+            Err(0) => None,
+            Err(covenant) => {
+                warn!(covenant, "Client sent an unknown covenant");
+                None
+            }
+        };
+    }
+
+    if let Some(multiplayer_state) = update.multiplayer_state {
+        match MultiplayerState::try_from(multiplayer_state) {
+            Ok(multiplayer_state) => status.multiplayer_state = multiplayer_state,
+            Err(multiplayer_state) => {
+                warn!(
+                    multiplayer_state,
+                    "Client sent an unknown multiplayer state"
+                );
+            }
+        }
+    }
+
+    if let Some(phantom_count) = update.phantom_count {
+        status.phantom_count = phantom_count;
+    }
+
+    entered_area
+}
+
+pub fn handle_update(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestUpdatePlayerStatus,
+) -> RequestUpdatePlayerStatusResponse {
+    let update = db.sessions().update(player.steam_id, |session| {
+        let was_alone = session.status.multiplayer_state == MultiplayerState::Solo;
+        let entered_area = apply_update(&mut session.status, request.status);
+        let is_alone = session.status.multiplayer_state == MultiplayerState::Solo;
+
+        (entered_area, !was_alone && is_alone)
+    });
+    let (entered_area, returned_alone) = update.unwrap_or_default();
+
+    debug!("Updated player status");
+
+    // Catch up on leave notifications that never arrived
+    if returned_alone {
+        world::leave_all(db, player.steam_id, LeaveReason::Ended);
+    }
+
+    if entered_area {
+        covenant::summon_guardian(db, player.steam_id);
+    }
+
+    RequestUpdatePlayerStatusResponse {}
+}
@@ -0,0 +1,194 @@
+use std::convert::TryFrom;
+
+use tracing::{info, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    PushRequestQuickMatchFound, QuickMatchData, QuickMatchPlayerData, QuickMatchRankingData,
+    RequestGetQuickMatchRanking, RequestGetQuickMatchRankingResponse, RequestRegisterQuickMatch,
+    RequestRegisterQuickMatchResponse, RequestSearchQuickMatch, RequestSearchQuickMatchResponse,
+    RequestSendQuickMatchResult, RequestSendQuickMatchResultResponse, RequestUnregisterQuickMatch,
+    RequestUnregisterQuickMatchResponse,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::quick_match::{
+    QuickMatch, QuickMatchMode, QuickMatchProgress, QuickMatchSearch,
+};
+use crate::service::game::{matching_parameters, Player};
+
+impl From<&QuickMatch> for QuickMatchData {
+    fn from(quick_match: &QuickMatch) -> Self {
+        Self {
+            match_id: quick_match.id,
+            mode: quick_match.mode.into(),
+            map_id: quick_match.map_id,
+            host_steamid: quick_match.host.to_string(),
+            players: quick_match
+                .players
+                .iter()
+                .map(|player| QuickMatchPlayerData {
+                    player_steamid: player.steam_id.to_string(),
+                    team: player.team,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn handle_register(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRegisterQuickMatch,
+) -> RequestRegisterQuickMatchResponse {
+    let mode = match QuickMatchMode::try_from(request.mode) {
+        Ok(mode) => mode,
+        Err(mode) => {
+            warn!(mode, "Client sent an unknown quick match mode");
+            return RequestRegisterQuickMatchResponse {};
+        }
+    };
+
+    let config = db.config();
+    let search = QuickMatchSearch {
+        steam_id: player.steam_id,
+        mode,
+        map_id: request.map_id,
+        matching: matching_parameters(db, player, request.matching_parameter),
+        rating: db.arena_ratings().get(player.steam_id, mode).rating,
+    };
+
+    let registered =
+        db.quick_matches()
+            .register(&config.matching_rules, &config.rating_window(), search);
+
+    match registered {
+        Ok(formed) => notify_formed(db, formed),
+        Err(e) => info!(error = %e, "Quick match registration refused"),
+    }
+
+    RequestRegisterQuickMatchResponse {}
+}
+
+/// Let everyone in newly formed matches know who they are fighting.
+fn notify_formed(db: &MatchmakingDb, formed: Vec<QuickMatch>) {
+    for quick_match in formed {
+        info!(
+            match_id = quick_match.id,
+            mode = ?quick_match.mode,
+            host = %quick_match.host,
+            "Formed quick match"
+        );
+
+        let push = PushRequestQuickMatchFound {
+            quick_match: QuickMatchData::from(&quick_match),
+        };
+
+        for member in &quick_match.players {
+            db.sessions().push(
+                member.steam_id,
+                MessageType::PushRequestQuickMatchFound,
+                &push,
+            );
+        }
+    }
+}
+
+pub fn handle_search(
+    db: &MatchmakingDb,
+    player: &Player,
+    _request: RequestSearchQuickMatch,
+) -> RequestSearchQuickMatchResponse {
+    // Rating windows widen while players wait, so the queue may allow matches it didn't before
+    let config = db.config();
+    let formed = db
+        .quick_matches()
+        .form_matches(&config.matching_rules, &config.rating_window());
+    notify_formed(db, formed);
+
+    RequestSearchQuickMatchResponse {
+        searching: db.quick_matches().is_queued(player.steam_id),
+        quick_match: db
+            .quick_matches()
+            .current_match(player.steam_id)
+            .as_ref()
+            .map(QuickMatchData::from),
+    }
+}
+
+pub fn handle_unregister(
+    db: &MatchmakingDb,
+    player: &Player,
+    _request: RequestUnregisterQuickMatch,
+) -> RequestUnregisterQuickMatchResponse {
+    db.quick_matches().unregister(player.steam_id);
+
+    RequestUnregisterQuickMatchResponse {}
+}
+
+pub fn handle_send_result(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestSendQuickMatchResult,
+) -> RequestSendQuickMatchResultResponse {
+    match db
+        .quick_matches()
+        .report(request.match_id, player.steam_id, request.winning_team)
+    {
+        Ok(QuickMatchProgress::Waiting) => {
+            info!(match_id = request.match_id, "Quick match result reported");
+        }
+        Ok(QuickMatchProgress::Finished(quick_match)) => {
+            info!(
+                match_id = quick_match.id,
+                winning_team = ?request.winning_team,
+                "Quick match finished"
+            );
+
+            db.arena_ratings().record(&quick_match);
+        }
+        Ok(QuickMatchProgress::Disputed(quick_match)) => {
+            info!(
+                match_id = quick_match.id,
+                "Quick match players reported different results, leaving it without one"
+            );
+        }
+        Err(e) => {
+            info!(match_id = request.match_id, error = %e, "Ignoring quick match result");
+        }
+    }
+
+    RequestSendQuickMatchResultResponse {}
+}
+
+pub fn handle_get_ranking(
+    db: &MatchmakingDb,
+    request: RequestGetQuickMatchRanking,
+) -> RequestGetQuickMatchRankingResponse {
+    let mode = match QuickMatchMode::try_from(request.mode) {
+        Ok(mode) => mode,
+        Err(mode) => {
+            warn!(mode, "Client sent an unknown quick match mode");
+            return RequestGetQuickMatchRankingResponse { ranking: vec![] };
+        }
+    };
+
+    let offset = request.offset as usize;
+    let count = (request.count as usize).min(db.config().arena_ranking_list_max);
+    let ranking = db.arena_ratings().leaderboard(mode, offset, count);
+
+    RequestGetQuickMatchRankingResponse {
+        ranking: ranking
+            .iter()
+            .enumerate()
+            .map(|(index, (steam_id, rating))| QuickMatchRankingData {
+                rank: (offset + index + 1) as u32,
+                player_steamid: steam_id.to_string(),
+                rating: rating.rating.round() as u32,
+                wins: rating.wins,
+                losses: rating.losses,
+                draws: rating.draws,
+            })
+            .collect(),
+    }
+}
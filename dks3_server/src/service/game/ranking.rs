@@ -0,0 +1,89 @@
+use tracing::{debug, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    RankingData, RequestCountRankingData, RequestCountRankingDataResponse, RequestGetCurrentRank,
+    RequestGetCurrentRankResponse, RequestGetRankingData, RequestGetRankingDataResponse,
+    RequestRegisterRankingData, RequestRegisterRankingDataResponse,
+};
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::ranking::RankedEntry;
+use crate::matchmaking::session::SteamId;
+use crate::service::game::Player;
+
+impl From<RankedEntry> for RankingData {
+    fn from(ranked: RankedEntry) -> Self {
+        Self {
+            rank: ranked.rank,
+            player_steamid: SteamId::new(ranked.entry.steam_id).to_string(),
+            score: ranked.entry.score,
+            data: ranked.entry.data,
+        }
+    }
+}
+
+pub fn handle_register(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRegisterRankingData,
+) -> RequestRegisterRankingDataResponse {
+    let board_id = request.board_id;
+    let result = db
+        .rankings()
+        .register(board_id, player.steam_id, request.score, request.data);
+
+    match result {
+        Ok(rank) => {
+            debug!(
+                board_id,
+                score = request.score,
+                rank,
+                "Registered ranking data"
+            );
+            RequestRegisterRankingDataResponse { rank }
+        }
+        Err(e) => {
+            warn!(board_id, error = %e, "Refused ranking data");
+            RequestRegisterRankingDataResponse {
+                rank: db.rankings().rank(board_id, player.steam_id).unwrap_or(0),
+            }
+        }
+    }
+}
+
+pub fn handle_get(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetRankingData,
+) -> RequestGetRankingDataResponse {
+    let count = (request.count as usize).min(db.config().ranking_list_max);
+    let entries = match request.rank {
+        Some(rank) => db.rankings().around_rank(request.board_id, rank, count),
+        None => db
+            .rankings()
+            .around_player(request.board_id, player.steam_id, count),
+    };
+
+    RequestGetRankingDataResponse {
+        data: entries.into_iter().map(RankingData::from).collect(),
+    }
+}
+
+pub fn handle_count(
+    db: &MatchmakingDb,
+    request: RequestCountRankingData,
+) -> RequestCountRankingDataResponse {
+    RequestCountRankingDataResponse {
+        count: db.rankings().count(request.board_id) as u32,
+    }
+}
+
+pub fn handle_get_current_rank(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetCurrentRank,
+) -> RequestGetCurrentRankResponse {
+    RequestGetCurrentRankResponse {
+        rank: db.rankings().rank(request.board_id, player.steam_id),
+    }
+}
@@ -0,0 +1,325 @@
+use std::convert::TryFrom;
+
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use dks3_proto::msg::frpg2_request::{
+    PushRequestAcceptSign, PushRequestRejectSign, PushRequestSummonSign, RequestAcceptSign,
+    RequestAcceptSignResponse, RequestCreateSign, RequestCreateSignResponse, RequestGetSignList,
+    RequestGetSignListResponse, RequestRejectSign, RequestRejectSignResponse, RequestRemoveSign,
+    RequestRemoveSignResponse, RequestSummonSign, RequestSummonSignResponse, RequestUpdateSign,
+    RequestUpdateSignResponse, SignInfo,
+};
+use dks3_proto::msg::MessageType;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::session::SteamId;
+use crate::matchmaking::sign::{AcceptError, NewSummonSign, SignType, SummonSign};
+use crate::service::game::{matching_parameters, Player};
+
+impl From<&SummonSign> for SignInfo {
+    fn from(sign: &SummonSign) -> Self {
+        Self {
+            sign_id: sign.id,
+            player_steamid: sign.owner.to_string(),
+            online_area_id: sign.online_area_id,
+            cell_id: sign.cell_id,
+            sign_type: sign.sign_type.into(),
+            sign_data: sign.data.to_vec(),
+        }
+    }
+}
+
+/// Let the host waiting on `sign`, if there is one, know that they won't be getting a summon.
+fn reject_waiting_host(db: &MatchmakingDb, sign: &SummonSign) {
+    if let Some(host) = sign.waiting_host() {
+        send_reject(db, host, sign.id, sign.owner);
+    }
+}
+
+fn send_reject(db: &MatchmakingDb, host: SteamId, sign_id: u64, owner: SteamId) {
+    let push = PushRequestRejectSign {
+        sign_id,
+        player_steamid: owner.to_string(),
+    };
+
+    db.sessions()
+        .push(host, MessageType::PushRequestRejectSign, &push);
+}
+
+/// Take down every sign `owner` has placed, for when they leave.
+pub fn remove_all_signs(db: &MatchmakingDb, owner: SteamId) {
+    for sign in db.signs().remove_all(owner) {
+        reject_waiting_host(db, &sign);
+    }
+}
+
+/// Free the signs `host` was summoning from, for when they leave.
+pub fn release_signs(db: &MatchmakingDb, host: SteamId) {
+    for sign in db.signs().release(host) {
+        info!(sign_id = sign.id, host = %host, "Host left, freeing sign");
+    }
+}
+
+pub fn handle_create(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestCreateSign,
+) -> RequestCreateSignResponse {
+    let sign_type = match SignType::try_from(request.sign_type) {
+        Ok(sign_type) => sign_type,
+        Err(sign_type) => {
+            warn!(sign_type, "Client sent an unknown sign type");
+            return RequestCreateSignResponse { sign_id: 0 };
+        }
+    };
+
+    let matching = matching_parameters(db, player, request.matching_parameter);
+
+    // The sign was placed with the password the player is using, so remember it for requests
+    // that don't carry one
+    if let Some(password) = &matching.password {
+        db.sessions().update(player.steam_id, |session| {
+            session.status.matching.password = Some(password.clone());
+        });
+    }
+
+    let (sign_id, replaced) = db.signs().create(NewSummonSign {
+        owner: player.steam_id,
+        sign_type,
+        online_area_id: request.online_area_id,
+        cell_id: request.cell_id,
+        matching,
+        data: Bytes::from(request.sign_data),
+    });
+
+    if let Some(replaced) = replaced {
+        reject_waiting_host(db, &replaced);
+    }
+
+    info!(
+        sign_id,
+        sign_type = ?sign_type,
+        online_area_id = request.online_area_id,
+        "Created summon sign"
+    );
+
+    RequestCreateSignResponse { sign_id }
+}
+
+pub fn handle_get_list(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestGetSignList,
+) -> RequestGetSignListResponse {
+    // A full world has no use for more summons
+    if !db
+        .worlds()
+        .has_room(player.steam_id, db.config().max_phantoms_per_world)
+    {
+        return RequestGetSignListResponse { signs: vec![] };
+    }
+
+    let matching = matching_parameters(db, player, request.matching_parameter);
+    let max_signs = (request.max_signs as usize).min(db.config().sign_list_max);
+    let mut rng = rand::thread_rng();
+    let mut signs = Vec::new();
+
+    for area in &request.search_areas {
+        let remaining = max_signs - signs.len();
+        if remaining == 0 {
+            break;
+        }
+
+        let count = (area.max_signs as usize).min(remaining);
+        let selected = db.signs().list(
+            &db.config().matching_rules,
+            player.steam_id,
+            &matching,
+            area.online_area_id,
+            count,
+            &mut rng,
+        );
+
+        signs.extend(selected.iter().map(SignInfo::from));
+    }
+
+    RequestGetSignListResponse { signs }
+}
+
+/// Whether the owner of a sign can be summoned: they can't already be in a multiplayer session or
+/// have an invader on the way.
+fn is_owner_free(db: &MatchmakingDb, owner: SteamId) -> bool {
+    !db.worlds().is_in_world(owner)
+        && db
+            .sessions()
+            .get(owner)
+            .map_or(false, |session| session.invasion.is_none())
+}
+
+/// Let the host know a summon they asked for didn't happen, before any sign owner was involved.
+fn refuse_summon(db: &MatchmakingDb, host: SteamId, sign_id: u64) {
+    let push = PushRequestRejectSign {
+        sign_id,
+        player_steamid: String::new(),
+    };
+
+    db.sessions()
+        .push(host, MessageType::PushRequestRejectSign, &push);
+}
+
+pub fn handle_summon(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestSummonSign,
+) -> RequestSummonSignResponse {
+    if !db
+        .worlds()
+        .has_room(player.steam_id, db.config().max_phantoms_per_world)
+    {
+        info!(sign_id = request.sign_id, "Host's world is full");
+        refuse_summon(db, player.steam_id, request.sign_id);
+
+        return RequestSummonSignResponse {};
+    }
+
+    // The request doesn't say who the host is matching as, so use what they last told us. Sign IDs
+    // are easy to guess, so this has to be checked as well as when listing signs.
+    let matching = db
+        .sessions()
+        .get(player.steam_id)
+        .map(|session| session.status.matching)
+        .unwrap_or_default();
+    let summoned = db.signs().summon(
+        &db.config().matching_rules,
+        player.steam_id,
+        &matching,
+        request.sign_id,
+    );
+
+    let sign = match summoned {
+        Ok((sign, timed_out)) => {
+            if let Some(timed_out) = timed_out {
+                info!(sign_id = sign.id, host = %timed_out, "Summon timed out");
+                send_reject(db, timed_out, sign.id, sign.owner);
+            }
+
+            sign
+        }
+        Err(e) => {
+            warn!(sign_id = request.sign_id, error = %e, "Unable to summon sign owner");
+            refuse_summon(db, player.steam_id, request.sign_id);
+
+            return RequestSummonSignResponse {};
+        }
+    };
+
+    if !is_owner_free(db, sign.owner) {
+        info!(sign_id = sign.id, owner = %sign.owner, "Sign owner is busy");
+        db.signs().reject(sign.owner, sign.id);
+        send_reject(db, player.steam_id, sign.id, sign.owner);
+
+        return RequestSummonSignResponse {};
+    }
+
+    let push = PushRequestSummonSign {
+        sign_id: sign.id,
+        player_steamid: player.steam_id.to_string(),
+        player_data: request.player_data,
+    };
+
+    if db
+        .sessions()
+        .push(sign.owner, MessageType::PushRequestSummonSign, &push)
+    {
+        info!(sign_id = sign.id, owner = %sign.owner, "Summoning sign owner");
+    } else {
+        // The owner has gone without us noticing yet, so the sign is no use to anyone
+        remove_all_signs(db, sign.owner);
+    }
+
+    RequestSummonSignResponse {}
+}
+
+pub fn handle_accept(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestAcceptSign,
+) -> RequestAcceptSignResponse {
+    let max_phantoms = db.config().max_phantoms_per_world;
+    let accepted = db
+        .signs()
+        .accept(db.worlds(), max_phantoms, player.steam_id, request.sign_id);
+
+    let (host, removed) = match accepted {
+        Ok(accepted) => accepted,
+        Err(AcceptError::NotPending) => {
+            warn!(
+                sign_id = request.sign_id,
+                "Accepted a summon that isn't pending"
+            );
+            return RequestAcceptSignResponse {};
+        }
+        Err(AcceptError::CantJoin { host, error }) => {
+            info!(sign_id = request.sign_id, host = %host, error = %error, "Summon can't go ahead");
+            send_reject(db, host, request.sign_id, player.steam_id);
+
+            return RequestAcceptSignResponse {};
+        }
+    };
+
+    // The owner is leaving for the host's world, so anyone waiting on their other signs is out
+    // of luck
+    for sign in removed.iter().filter(|sign| sign.id != request.sign_id) {
+        reject_waiting_host(db, sign);
+    }
+
+    let push = PushRequestAcceptSign {
+        sign_id: request.sign_id,
+        player_steamid: player.steam_id.to_string(),
+    };
+    db.sessions()
+        .push(host, MessageType::PushRequestAcceptSign, &push);
+
+    info!(sign_id = request.sign_id, host = %host, "Summon accepted");
+
+    RequestAcceptSignResponse {}
+}
+
+pub fn handle_reject(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRejectSign,
+) -> RequestRejectSignResponse {
+    if let Some(host) = db.signs().reject(player.steam_id, request.sign_id) {
+        send_reject(db, host, request.sign_id, player.steam_id);
+        info!(sign_id = request.sign_id, host = %host, "Summon rejected");
+    }
+
+    RequestRejectSignResponse {}
+}
+
+pub fn handle_update(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestUpdateSign,
+) -> RequestUpdateSignResponse {
+    let matching = matching_parameters(db, player, request.matching_parameter);
+    db.signs()
+        .update(player.steam_id, request.sign_id, matching);
+
+    RequestUpdateSignResponse {}
+}
+
+pub fn handle_remove(
+    db: &MatchmakingDb,
+    player: &Player,
+    request: RequestRemoveSign,
+) -> RequestRemoveSignResponse {
+    if let Some(sign) = db.signs().remove(player.steam_id, request.sign_id) {
+        reject_waiting_host(db, &sign);
+        info!(sign_id = sign.id, "Removed summon sign");
+    }
+
+    RequestRemoveSignResponse {}
+}
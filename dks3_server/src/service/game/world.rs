@@ -0,0 +1,26 @@
+use tracing::info;
+
+use crate::context::MatchmakingDb;
+use crate::matchmaking::session::SteamId;
+use crate::matchmaking::world::{Departure, LeaveReason};
+
+pub fn log_departure(departure: &Departure) {
+    let stayed_secs = (departure.left_at - departure.phantom.joined_at).num_seconds();
+
+    info!(
+        host = %departure.host,
+        phantom = %departure.phantom.steam_id,
+        role = ?departure.phantom.role,
+        reason = ?departure.reason,
+        stayed_secs,
+        "Phantom left world"
+    );
+}
+
+/// Take the player out of any multiplayer session they are in, along with any phantoms in their
+/// own world.
+pub fn leave_all(db: &MatchmakingDb, steam_id: SteamId, reason: LeaveReason) {
+    for departure in db.worlds().leave_all(steam_id, reason) {
+        log_departure(&departure);
+    }
+}
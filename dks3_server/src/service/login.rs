@@ -1,15 +1,16 @@
 use async_trait::async_trait;
 use bytes::BytesMut;
 use prost::Message;
-use tracing::info;
+use tracing::{error, info};
 
 use dks3_proto::frame::{CipherMode, Frame};
 use dks3_proto::msg::frpg2_request::RequestQueryLoginServerInfo;
 use dks3_proto::msg::frpg2_request::RequestQueryLoginServerInfoResponse;
 
 use crate::context::MatchmakingDb;
+use crate::matchmaking::session::{Service, SteamId};
 use crate::net::server::{ConnectionHandler, TcpServer};
-use crate::net::Connection;
+use crate::net::{CipherPair, Connection};
 use crate::{net, Config};
 use std::time::Duration;
 
@@ -52,8 +53,24 @@ impl ConnectionHandler<MatchmakingDb> for LoginConnectionHandler {
         let server_info_req = self.read_message::<RequestQueryLoginServerInfo>(conn).await;
 
         /* Could check steam ID, versionnum, etc. here */
+        tracing::Span::current().record("steamid", &server_info_req.steamid.as_str());
         info!(steamid = %server_info_req.steamid, version = %server_info_req.versionnum, "Client connected");
 
+        let steam_id = match server_info_req.steamid.parse::<SteamId>() {
+            Ok(steam_id) => steam_id,
+            Err(e) => {
+                error!(steamid = %server_info_req.steamid, error = %e, "Client sent an invalid steamid");
+                return;
+            }
+        };
+
+        let _registration = context.sessions().register(
+            steam_id,
+            Service::Login,
+            conn.metadata().id,
+            Some(server_info_req.versionnum),
+        );
+
         let config = context.config();
         let server_info = RequestQueryLoginServerInfoResponse {
             serverip: config.server_ip.clone(),
@@ -73,7 +90,13 @@ pub fn create_login_service(
     let bind_addr = format!("{}:{}", config.server_ip, config.login_port);
     let inbound_cipher_mode = CipherMode::rsa_pkcs1_oeap(config.rsa_private_key.as_bytes());
     let outbound_cipher_mode = CipherMode::rsa_x931(config.rsa_private_key.as_bytes());
-    let ciphers = (inbound_cipher_mode, outbound_cipher_mode);
-
-    TcpServer::new(bind_addr, ciphers, db.clone())
+    let ciphers = CipherPair::new(inbound_cipher_mode, outbound_cipher_mode);
+
+    TcpServer::new(
+        bind_addr,
+        ciphers,
+        config.connection_limits(),
+        config.login_proxy_protocol,
+        db.clone(),
+    )
 }
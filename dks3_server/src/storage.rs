@@ -0,0 +1,99 @@
+//! Persistent state that has to survive restarts, kept as JSON files under the data directory.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::error;
+
+/// How often data that has changed is written out.
+pub const WRITE_INTERVAL_SECS: u64 = 5;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("unable to access data file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("data file is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+/// Read the value stored at `path`, or None if nothing has been stored there yet.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, StorageError> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Store `value` at `path`, creating the data directory if needed.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<(), StorageError> {
+    save_bytes(path, &serde_json::to_vec(value)?)
+}
+
+/// Store `data` at `path` as it is, creating the data directory if needed. The file is written to
+/// the side and renamed into place so a crash part way through never leaves it truncated.
+pub fn save_bytes(path: &Path, data: &[u8]) -> Result<(), StorageError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// Whether data has changed since it was last stored.
+#[derive(Clone, Debug, Default)]
+pub struct Dirty(Arc<AtomicBool>);
+
+impl Dirty {
+    pub fn mark(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Store data at `path` in the background whenever `dirty` has been marked, checking every few
+/// seconds. `snapshot` takes a copy of the data and the file is written on the blocking pool, so
+/// requests never wait on the disk. If writing fails the data stays marked and is tried again.
+pub fn spawn_writer<T, F>(path: PathBuf, dirty: Dirty, snapshot: F)
+where
+    T: Serialize + Send + 'static,
+    F: Fn() -> T + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(WRITE_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            if !dirty.take() {
+                continue;
+            }
+
+            let value = snapshot();
+            let target = path.clone();
+            let error = match tokio::task::spawn_blocking(move || save(&target, &value)).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+
+            error!(path = %path.display(), error = %error, "Unable to store data, will try again");
+            dirty.mark();
+        }
+    });
+}